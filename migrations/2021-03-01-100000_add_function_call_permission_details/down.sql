@@ -0,0 +1,6 @@
+DROP INDEX access_keys_receiver_id_idx;
+
+ALTER TABLE access_keys
+    DROP COLUMN allowance,
+    DROP COLUMN receiver_id,
+    DROP COLUMN method_names;
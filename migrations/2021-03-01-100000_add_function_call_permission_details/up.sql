@@ -0,0 +1,7 @@
+-- Details of the FUNCTION_CALL permission, NULL for FULL_ACCESS and NOT_APPLICABLE
+ALTER TABLE access_keys
+    ADD COLUMN allowance numeric(45, 0), -- NULL for FUNCTION_CALL means unlimited allowance
+    ADD COLUMN receiver_id text,
+    ADD COLUMN method_names text[];
+
+CREATE INDEX access_keys_receiver_id_idx ON access_keys (receiver_id);
@@ -0,0 +1,24 @@
+-- One row per live access key, maintained by the indexer alongside the `access_keys` event log
+CREATE TABLE current_access_keys (
+    account_id text NOT NULL,
+    public_key text NOT NULL,
+    "permission" access_key_permission_type NOT NULL,
+    allowance numeric(45, 0),
+    receiver_id text,
+    method_names text[],
+    receipt_hash text NOT NULL, -- receipt which has added the key
+    block_height numeric(20) NOT NULL,
+    CONSTRAINT current_access_keys_pk PRIMARY KEY (account_id, public_key)
+);
+CREATE INDEX current_access_keys_public_key_idx ON current_access_keys (public_key);
+
+-- Fill the table from the already indexed events using "last successful action wins" rule
+INSERT INTO current_access_keys
+SELECT account_id, public_key, "permission", allowance, receiver_id, method_names, receipt_hash, block_height
+FROM (
+    SELECT DISTINCT ON (account_id, public_key) *
+    FROM access_keys
+    WHERE status = 'SUCCESS'
+    ORDER BY account_id, public_key, block_height DESC
+) AS last_actions
+WHERE "action" = 'ADD';
@@ -0,0 +1,9 @@
+-- Execution outcomes received before the related receipts were stored in `access_keys`.
+-- They are applied and removed once the receipts are inserted
+CREATE TABLE pending_outcomes (
+    receipt_hash text NOT NULL,
+    status execution_status_type NOT NULL,
+    block_height numeric(20) NOT NULL, -- height of the block the outcome was received in
+    CONSTRAINT pending_outcomes_pk PRIMARY KEY (receipt_hash)
+);
+CREATE INDEX pending_outcomes_block_height_idx ON pending_outcomes (block_height);
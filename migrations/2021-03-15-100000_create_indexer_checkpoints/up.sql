@@ -0,0 +1,9 @@
+-- Blocks which are fully indexed. A row is written in the same transaction with the rest of the block data
+CREATE TABLE indexer_checkpoints (
+    block_height numeric(20) NOT NULL,
+    block_hash text NOT NULL,
+    prev_block_hash text NOT NULL,
+    block_timestamp numeric(20) NOT NULL, -- nanoseconds
+    CONSTRAINT indexer_checkpoints_pk PRIMARY KEY (block_height)
+);
+CREATE INDEX indexer_checkpoints_prev_block_hash_idx ON indexer_checkpoints (prev_block_hash);
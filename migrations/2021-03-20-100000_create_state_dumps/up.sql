@@ -0,0 +1,8 @@
+-- Blocks the state was dumped at by `dump-state` command. The dumped state doesn't include
+-- the changes of the block itself, so the streaming should be resumed from this block
+CREATE TABLE state_dumps (
+    block_height numeric(20) NOT NULL,
+    block_hash text NOT NULL,
+    block_timestamp numeric(20) NOT NULL, -- nanoseconds
+    CONSTRAINT state_dumps_pk PRIMARY KEY (block_height)
+);
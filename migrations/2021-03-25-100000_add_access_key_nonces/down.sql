@@ -0,0 +1,5 @@
+ALTER TABLE current_access_keys
+    DROP COLUMN nonce;
+
+ALTER TABLE access_keys
+    DROP COLUMN nonce;
@@ -0,0 +1,7 @@
+-- Initial nonce for ADD actions, NULL for DELETE
+ALTER TABLE access_keys
+    ADD COLUMN nonce numeric(20, 0);
+
+-- The latest known nonce, advanced by the transactions signed with the key
+ALTER TABLE current_access_keys
+    ADD COLUMN nonce numeric(20, 0);
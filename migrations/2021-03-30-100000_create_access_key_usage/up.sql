@@ -0,0 +1,12 @@
+-- Transactions signed by the access keys, the history is pruned according to
+-- `--key-usage-retention-blocks` flag of `run` command
+CREATE TABLE access_key_usage (
+    transaction_hash text NOT NULL,
+    account_id text NOT NULL, -- signer of the transaction
+    public_key text NOT NULL,
+    receiver_id text NOT NULL, -- the contract (or account) the transaction is sent to
+    block_height numeric(20) NOT NULL,
+    CONSTRAINT access_key_usage_pk PRIMARY KEY (transaction_hash)
+);
+CREATE INDEX access_key_usage_key_idx ON access_key_usage (account_id, public_key, block_height);
+CREATE INDEX access_key_usage_block_height_idx ON access_key_usage (block_height);
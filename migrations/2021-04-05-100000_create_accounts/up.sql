@@ -0,0 +1,13 @@
+-- Lifecycle of the accounts. The creation details are NULL for the accounts which were
+-- created before the indexer started (e.g. the ones added by `dump-state`)
+CREATE TABLE accounts (
+    account_id text NOT NULL,
+    is_implicit boolean NOT NULL,
+    created_by_receipt_hash text,
+    created_by_account_id text, -- predecessor of the receipt which created the account
+    created_at_block_height numeric(20),
+    deleted_by_receipt_hash text, -- NULL if the account exists
+    deleted_at_block_height numeric(20),
+    CONSTRAINT accounts_pk PRIMARY KEY (account_id)
+);
+CREATE INDEX accounts_created_by_account_id_idx ON accounts (created_by_account_id);
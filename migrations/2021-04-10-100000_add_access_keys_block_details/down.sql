@@ -0,0 +1,5 @@
+DROP INDEX access_keys_block_hash_idx;
+
+ALTER TABLE access_keys
+    DROP COLUMN block_hash,
+    DROP COLUMN block_timestamp;
@@ -0,0 +1,20 @@
+-- Block the action was included in, NULL for the rows indexed before the columns were introduced
+-- if the block is unknown
+ALTER TABLE access_keys
+    ADD COLUMN block_hash text,
+    ADD COLUMN block_timestamp numeric(20, 0); -- nanoseconds since the Unix epoch
+
+UPDATE access_keys
+SET block_hash = indexer_checkpoints.block_hash,
+    block_timestamp = indexer_checkpoints.block_timestamp
+FROM indexer_checkpoints
+WHERE access_keys.block_height = indexer_checkpoints.block_height;
+
+UPDATE access_keys
+SET block_hash = state_dumps.block_hash,
+    block_timestamp = state_dumps.block_timestamp
+FROM state_dumps
+WHERE access_keys.block_height = state_dumps.block_height
+    AND access_keys.block_hash IS NULL;
+
+CREATE INDEX access_keys_block_hash_idx ON access_keys (block_hash);
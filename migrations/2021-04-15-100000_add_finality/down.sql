@@ -0,0 +1,13 @@
+DROP INDEX indexer_checkpoints_not_final_idx;
+DROP INDEX indexer_checkpoints_block_height_idx;
+
+DELETE FROM indexer_checkpoints WHERE NOT is_final;
+ALTER TABLE indexer_checkpoints
+    DROP CONSTRAINT indexer_checkpoints_pk,
+    ADD CONSTRAINT indexer_checkpoints_pk PRIMARY KEY (block_height);
+
+ALTER TABLE indexer_checkpoints
+    DROP COLUMN is_final;
+
+ALTER TABLE access_keys
+    DROP COLUMN is_final;
@@ -0,0 +1,19 @@
+-- The rows become final once their block is final (see `last_final_block` of the block header).
+-- The rows of the blocks which never become final are rolled back. The existing rows are
+-- considered as final
+ALTER TABLE access_keys
+    ADD COLUMN is_final boolean NOT NULL DEFAULT true;
+ALTER TABLE access_keys
+    ALTER COLUMN is_final DROP DEFAULT;
+
+ALTER TABLE indexer_checkpoints
+    ADD COLUMN is_final boolean NOT NULL DEFAULT true;
+ALTER TABLE indexer_checkpoints
+    ALTER COLUMN is_final DROP DEFAULT;
+
+-- Blocks of different forks may have the same height
+ALTER TABLE indexer_checkpoints
+    DROP CONSTRAINT indexer_checkpoints_pk,
+    ADD CONSTRAINT indexer_checkpoints_pk PRIMARY KEY (block_hash);
+CREATE INDEX indexer_checkpoints_block_height_idx ON indexer_checkpoints (block_height);
+CREATE INDEX indexer_checkpoints_not_final_idx ON indexer_checkpoints (block_height) WHERE NOT is_final;
@@ -0,0 +1,39 @@
+-- Webhooks called when the matching access key action becomes SUCCESS.
+-- Every NULL filter matches any value
+CREATE TABLE webhooks (
+    id serial NOT NULL,
+    url text NOT NULL,
+    secret text NOT NULL, -- HMAC-SHA256 key of the payload signature
+    account_id text,
+    public_key text,
+    "action" access_key_action_type,
+    "permission" access_key_permission_type,
+    CONSTRAINT webhooks_pk PRIMARY KEY (id),
+    -- Watching every access key of the network is not supported
+    CONSTRAINT webhooks_filter_check CHECK (account_id IS NOT NULL OR public_key IS NOT NULL)
+);
+CREATE INDEX webhooks_account_id_idx ON webhooks (account_id);
+CREATE INDEX webhooks_public_key_idx ON webhooks (public_key);
+
+CREATE TYPE webhook_delivery_status_type AS ENUM ('PENDING', 'DELIVERED', 'FAILED');
+
+-- Delivery queue and log of the webhooks
+CREATE TABLE webhook_deliveries (
+    id bigserial NOT NULL,
+    webhook_id integer NOT NULL REFERENCES webhooks (id) ON DELETE CASCADE,
+    receipt_hash text NOT NULL,
+    account_id text NOT NULL,
+    public_key text NOT NULL,
+    "action" access_key_action_type NOT NULL,
+    payload text NOT NULL,
+    status webhook_delivery_status_type NOT NULL,
+    attempts integer NOT NULL,
+    next_attempt_at bigint NOT NULL, -- unix timestamp in milliseconds
+    response_status integer,
+    last_error text,
+    delivered_at bigint, -- unix timestamp in milliseconds
+    CONSTRAINT webhook_deliveries_pk PRIMARY KEY (id),
+    -- The same receipt may be stored in the blocks of different forks, it is delivered once
+    CONSTRAINT webhook_deliveries_action_uniq UNIQUE (webhook_id, receipt_hash, account_id, public_key, "action")
+);
+CREATE INDEX webhook_deliveries_pending_idx ON webhook_deliveries (next_attempt_at) WHERE status = 'PENDING';
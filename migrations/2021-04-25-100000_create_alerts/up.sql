@@ -0,0 +1,14 @@
+-- Security alerts raised by the rules evaluated on the successful access key actions
+CREATE TABLE alerts (
+    rule text NOT NULL,
+    account_id text NOT NULL,
+    public_key text NOT NULL, -- the key the alert is raised for
+    receipt_hash text NOT NULL,
+    block_height numeric(20) NOT NULL,
+    block_timestamp numeric(20),
+    message text NOT NULL,
+    -- The same receipt may be stored in the blocks of different forks, it is alerted once
+    CONSTRAINT alerts_pk PRIMARY KEY (rule, account_id, public_key, receipt_hash)
+);
+CREATE INDEX alerts_account_id_idx ON alerts (account_id);
+CREATE INDEX alerts_block_height_idx ON alerts (block_height);
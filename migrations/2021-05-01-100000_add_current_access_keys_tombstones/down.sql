@@ -0,0 +1,3 @@
+DELETE FROM current_access_keys WHERE is_deleted;
+ALTER TABLE current_access_keys
+    DROP COLUMN is_deleted;
@@ -0,0 +1,17 @@
+-- The latest successful `DELETE` action of the pair is kept as a tombstone, so an older `ADD`
+-- applied out of order can't bring the key back
+ALTER TABLE current_access_keys
+    ADD COLUMN is_deleted boolean NOT NULL DEFAULT false;
+ALTER TABLE current_access_keys
+    ALTER COLUMN is_deleted DROP DEFAULT;
+
+INSERT INTO current_access_keys
+SELECT account_id, public_key, "permission", allowance, receiver_id, method_names, receipt_hash, block_height, NULL, true
+FROM (
+    SELECT DISTINCT ON (account_id, public_key) *
+    FROM access_keys
+    WHERE status = 'SUCCESS'
+    ORDER BY account_id, public_key, block_height DESC
+) AS last_actions
+WHERE "action" = 'DELETE'
+ON CONFLICT DO NOTHING;
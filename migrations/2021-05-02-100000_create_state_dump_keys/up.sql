@@ -0,0 +1,7 @@
+-- The keys met in the state by the merging `dump-state`. The known keys which are missing here
+-- are deleted once the whole state is read. The table is emptied after every dump
+CREATE UNLOGGED TABLE state_dump_keys (
+    account_id text NOT NULL,
+    public_key text NOT NULL,
+    CONSTRAINT state_dump_keys_pk PRIMARY KEY (account_id, public_key)
+);
@@ -0,0 +1,17 @@
+DROP TABLE staged_changes;
+
+DROP INDEX indexer_checkpoints_last_final_block_hash_idx;
+ALTER TABLE indexer_checkpoints
+    DROP COLUMN last_final_block_hash;
+
+-- Only one row of the action is kept, the final one if any
+DELETE FROM access_keys AS duplicate
+USING access_keys AS kept
+WHERE duplicate.public_key = kept.public_key
+    AND duplicate.account_id = kept.account_id
+    AND duplicate."action" = kept."action"
+    AND duplicate.receipt_hash = kept.receipt_hash
+    AND (duplicate.is_final, duplicate.ctid) < (kept.is_final, kept.ctid);
+DROP INDEX access_keys_action_block_uniq;
+ALTER TABLE access_keys
+    ADD CONSTRAINT access_keys_pk PRIMARY KEY (public_key, account_id, "action", receipt_hash);
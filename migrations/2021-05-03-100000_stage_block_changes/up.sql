@@ -0,0 +1,22 @@
+-- The same receipt may be included in the blocks of different forks. Every block keeps its own
+-- rows, so rolling back one of the blocks never touches the rows of the others. The rows indexed
+-- before `block_hash` was introduced may have NULL block hash, they are kept as they are
+ALTER TABLE access_keys
+    DROP CONSTRAINT access_keys_pk;
+CREATE UNIQUE INDEX access_keys_action_block_uniq
+    ON access_keys (public_key, account_id, "action", receipt_hash, block_hash);
+
+-- Any block names the last final block, so the final chain is known even if the blocks
+-- naming it are rolled back later
+ALTER TABLE indexer_checkpoints
+    ADD COLUMN last_final_block_hash text;
+CREATE INDEX indexer_checkpoints_last_final_block_hash_idx ON indexer_checkpoints (last_final_block_hash);
+
+-- Changes of the not yet final blocks (outcomes, accounts, nonces, key usage) which are applied
+-- once the block is final and dropped along with the block if it is rolled back
+CREATE TABLE staged_changes (
+    block_hash text NOT NULL,
+    block_height numeric(20, 0) NOT NULL,
+    changes jsonb NOT NULL,
+    CONSTRAINT staged_changes_pk PRIMARY KEY (block_hash)
+);
@@ -0,0 +1,5 @@
+ALTER TABLE current_access_keys
+    DROP COLUMN action_index;
+
+ALTER TABLE access_keys
+    DROP COLUMN action_index;
@@ -0,0 +1,45 @@
+-- Position of the action in its receipt. The same receipt may both add and delete the key
+-- (e.g. `AddKey` followed by `DeleteAccount`), so the actions of the same block are ordered by
+-- (block_height, action_index)
+ALTER TABLE access_keys
+    ADD COLUMN action_index integer NOT NULL DEFAULT 0;
+ALTER TABLE access_keys
+    ALTER COLUMN action_index DROP DEFAULT;
+
+ALTER TABLE current_access_keys
+    ADD COLUMN action_index integer NOT NULL DEFAULT 0;
+ALTER TABLE current_access_keys
+    ALTER COLUMN action_index DROP DEFAULT;
+
+-- The position of the existing actions is unknown. The key deleted by the same receipt which
+-- added it was deleted after it had been added (e.g. by `DeleteAccount`)
+UPDATE access_keys
+SET action_index = 1
+WHERE "action" = 'DELETE'
+    AND EXISTS (
+        SELECT 1
+        FROM access_keys AS added
+        WHERE added.receipt_hash = access_keys.receipt_hash
+            AND added.account_id = access_keys.account_id
+            AND added.public_key = access_keys.public_key
+            AND added."action" = 'ADD'
+    );
+
+-- Such keys may have been left live, they are deleted
+UPDATE current_access_keys
+SET "permission" = 'NOT_APPLICABLE',
+    allowance = NULL,
+    receiver_id = NULL,
+    method_names = NULL,
+    nonce = NULL,
+    is_deleted = true,
+    action_index = deleted.action_index
+FROM access_keys AS deleted
+WHERE deleted.receipt_hash = current_access_keys.receipt_hash
+    AND deleted.account_id = current_access_keys.account_id
+    AND deleted.public_key = current_access_keys.public_key
+    AND deleted."action" = 'DELETE'
+    AND deleted.status = 'SUCCESS'
+    AND deleted.is_final
+    AND deleted.action_index = 1
+    AND NOT current_access_keys.is_deleted;
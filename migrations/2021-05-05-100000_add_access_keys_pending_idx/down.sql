@@ -0,0 +1 @@
+DROP INDEX access_keys_pending_idx;
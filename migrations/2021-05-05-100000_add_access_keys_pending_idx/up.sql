@@ -0,0 +1 @@
+CREATE INDEX access_keys_pending_idx ON access_keys (block_height) WHERE status = 'PENDING';
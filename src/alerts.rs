@@ -0,0 +1,430 @@
+use std::collections::{HashMap, HashSet};
+
+use diesel::{ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use itertools::Itertools;
+use tracing::warn;
+
+use crate::db::enums::{AccessKeyAction, AccessKeyPermission, ExecutionStatus};
+use crate::db::{AccessKey, Alert, CurrentAccessKey};
+use crate::{schema, INDEXER_FOR_WALLET};
+
+/// Successful access key actions of a single receipt on a single account
+pub(crate) struct ReceiptActions<'a> {
+    pub account_id: &'a str,
+    pub receipt_hash: &'a str,
+    pub actions: Vec<&'a AccessKey>,
+    /// Keys present on the account before the block
+    pub keys_before: Vec<&'a CurrentAccessKey>,
+    /// Keys present on the account after the block
+    pub account_keys: Vec<&'a CurrentAccessKey>,
+}
+
+impl<'a> ReceiptActions<'a> {
+    /// `FULL_ACCESS` keys added by the receipt which are still present on the account
+    fn added_full_access_keys(&self) -> Vec<&'a AccessKey> {
+        self.actions
+            .iter()
+            .copied()
+            .filter(|access_key| {
+                matches!(access_key.action, AccessKeyAction::Add)
+                    && matches!(access_key.permission, AccessKeyPermission::FullAccess)
+                    && self
+                        .account_keys
+                        .iter()
+                        .any(|account_key| account_key.public_key == access_key.public_key)
+            })
+            .collect()
+    }
+}
+
+/// Rule raising alerts on suspicious access key actions
+pub(crate) trait AlertRule: Send + Sync {
+    /// Name of the rule stored along with the alert
+    fn name(&self) -> &'static str;
+
+    fn evaluate(&self, receipt: &ReceiptActions) -> Vec<Alert>;
+}
+
+/// Destination of the alerts, called once the block with the alerts is stored
+pub(crate) trait AlertSink: Send + Sync {
+    fn emit(&self, alert: &Alert);
+}
+
+/// `FULL_ACCESS` key is added to the account which had more than `max_existing_keys` other keys
+/// before the block
+pub(crate) struct FullAccessKeyOnCrowdedAccount {
+    pub max_existing_keys: usize,
+}
+
+impl AlertRule for FullAccessKeyOnCrowdedAccount {
+    fn name(&self) -> &'static str {
+        "FULL_ACCESS_KEY_ON_CROWDED_ACCOUNT"
+    }
+
+    fn evaluate(&self, receipt: &ReceiptActions) -> Vec<Alert> {
+        receipt
+            .added_full_access_keys()
+            .into_iter()
+            .filter_map(|access_key| {
+                let existing_keys = receipt
+                    .keys_before
+                    .iter()
+                    .filter(|account_key| account_key.public_key != access_key.public_key)
+                    .count();
+                if existing_keys > self.max_existing_keys {
+                    Some(Alert::new(
+                        self.name(),
+                        access_key,
+                        format!(
+                            "FULL_ACCESS key is added to {} which has {} other keys",
+                            receipt.account_id, existing_keys
+                        ),
+                    ))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+/// `FULL_ACCESS` key is added and the other `FULL_ACCESS` keys are deleted by the same receipt,
+/// so the added keys are the only `FULL_ACCESS` keys left on the account. The permission of
+/// the deleted keys is taken from the keys of the account before the block
+pub(crate) struct AccountTakeover;
+
+impl AlertRule for AccountTakeover {
+    fn name(&self) -> &'static str {
+        "ACCOUNT_TAKEOVER"
+    }
+
+    fn evaluate(&self, receipt: &ReceiptActions) -> Vec<Alert> {
+        let added_full_access_keys = receipt.added_full_access_keys();
+        let added_public_keys: HashSet<&str> = added_full_access_keys
+            .iter()
+            .map(|access_key| access_key.public_key.as_str())
+            .collect();
+        let deleted_full_access_keys = receipt
+            .actions
+            .iter()
+            .filter(|access_key| {
+                matches!(access_key.action, AccessKeyAction::Delete)
+                    && !added_public_keys.contains(access_key.public_key.as_str())
+                    && receipt.keys_before.iter().any(|account_key| {
+                        account_key.public_key == access_key.public_key
+                            && matches!(account_key.permission, AccessKeyPermission::FullAccess)
+                    })
+            })
+            .count();
+        let other_full_access_keys_left = receipt.account_keys.iter().any(|account_key| {
+            matches!(account_key.permission, AccessKeyPermission::FullAccess)
+                && !added_public_keys.contains(account_key.public_key.as_str())
+        });
+        if deleted_full_access_keys == 0 || other_full_access_keys_left {
+            return vec![];
+        }
+        added_full_access_keys
+            .into_iter()
+            .map(|access_key| {
+                Alert::new(
+                    self.name(),
+                    access_key,
+                    format!(
+                        "FULL_ACCESS key is added to {} and {} other FULL_ACCESS keys are deleted by receipt {}, no other FULL_ACCESS keys are left",
+                        receipt.account_id, deleted_full_access_keys, receipt.receipt_hash
+                    ),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Writes the alerts to the log
+pub(crate) struct LogSink;
+
+impl AlertSink for LogSink {
+    fn emit(&self, alert: &Alert) {
+        warn!(
+            target: INDEXER_FOR_WALLET,
+            "Security alert {} at block #{}: {} (key {}, receipt {})",
+            alert.rule,
+            alert.block_height,
+            alert.message,
+            alert.public_key,
+            alert.receipt_hash
+        );
+    }
+}
+
+pub(crate) struct Alerting {
+    pub rules: Vec<Box<dyn AlertRule>>,
+    pub sinks: Vec<Box<dyn AlertSink>>,
+}
+
+impl Alerting {
+    /// New rules and sinks are plugged in here
+    pub fn new(max_existing_keys: usize) -> Self {
+        Self {
+            rules: vec![
+                Box::new(FullAccessKeyOnCrowdedAccount { max_existing_keys }),
+                Box::new(AccountTakeover),
+            ],
+            sinks: vec![Box::new(LogSink)],
+        }
+    }
+
+    pub fn emit(&self, alerts: &[Alert]) {
+        for alert in alerts {
+            for sink in &self.sinks {
+                sink.emit(alert);
+            }
+        }
+    }
+}
+
+/// Live keys of the accounts with the access key actions which became SUCCESS, grouped by account
+pub(crate) fn load_account_keys(
+    conn: &PgConnection,
+    access_keys: &[AccessKey],
+) -> diesel::QueryResult<HashMap<String, Vec<CurrentAccessKey>>> {
+    let account_ids: Vec<&str> = access_keys
+        .iter()
+        .filter(|access_key| matches!(access_key.status, ExecutionStatus::Success))
+        .map(|access_key| access_key.account_id.as_str())
+        .unique()
+        .collect();
+    if account_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+    Ok(schema::current_access_keys::table
+        .filter(schema::current_access_keys::dsl::account_id.eq_any(account_ids))
+        .filter(schema::current_access_keys::dsl::is_deleted.eq(false))
+        .load::<CurrentAccessKey>(conn)?
+        .into_iter()
+        .into_group_map_by(|account_key| account_key.account_id.clone()))
+}
+
+/// Evaluates the rules on the access key actions which became SUCCESS and stores the alerts.
+/// Is expected to be called within the transaction which applies the final block, after
+/// `current_access_keys` is updated, `keys_before` is loaded by `load_account_keys` before
+/// the update. Returns the alerts which were not raised before
+pub(crate) fn evaluate_alerts(
+    conn: &PgConnection,
+    rules: &[Box<dyn AlertRule>],
+    access_keys: &[AccessKey],
+    keys_before: &HashMap<String, Vec<CurrentAccessKey>>,
+) -> diesel::QueryResult<Vec<Alert>> {
+    // The same action may be both inserted and updated within the block
+    let receipts: HashMap<(&str, &str), Vec<&AccessKey>> = access_keys
+        .iter()
+        .filter(|access_key| matches!(access_key.status, ExecutionStatus::Success))
+        .unique_by(|access_key| {
+            (
+                &access_key.receipt_hash,
+                &access_key.account_id,
+                &access_key.public_key,
+                access_key.action.clone(),
+            )
+        })
+        .into_group_map_by(|access_key| {
+            (
+                access_key.account_id.as_str(),
+                access_key.receipt_hash.as_str(),
+            )
+        });
+    if receipts.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let account_keys = load_account_keys(conn, access_keys)?;
+    let alerts: Vec<Alert> = receipts
+        .into_iter()
+        .flat_map(|((account_id, receipt_hash), actions)| {
+            let receipt = ReceiptActions {
+                account_id,
+                receipt_hash,
+                actions,
+                keys_before: keys_before
+                    .get(account_id)
+                    .map(|account_keys| account_keys.iter().collect())
+                    .unwrap_or_default(),
+                account_keys: account_keys
+                    .get(account_id)
+                    .map(|account_keys| account_keys.iter().collect())
+                    .unwrap_or_default(),
+            };
+            rules
+                .iter()
+                .flat_map(|rule| rule.evaluate(&receipt))
+                .collect::<Vec<Alert>>()
+        })
+        .collect();
+    if alerts.is_empty() {
+        return Ok(vec![]);
+    }
+    diesel::insert_into(schema::alerts::table)
+        .values(alerts)
+        .on_conflict_do_nothing()
+        .get_results(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use bigdecimal::BigDecimal;
+
+    use super::*;
+    use crate::test_utils;
+
+    const ACCOUNT_ID: &str = "alice.near";
+    const RECEIPT_HASH: &str = "receipt";
+
+    fn action(action: AccessKeyAction, key_seed: &str) -> AccessKey {
+        let permission = match action {
+            AccessKeyAction::Add => AccessKeyPermission::FullAccess,
+            AccessKeyAction::Delete => AccessKeyPermission::NotApplicable,
+        };
+        AccessKey {
+            public_key: test_utils::public_key(key_seed).to_string(),
+            account_id: ACCOUNT_ID.to_string(),
+            action,
+            status: ExecutionStatus::Success,
+            receipt_hash: RECEIPT_HASH.to_string(),
+            block_height: BigDecimal::from(2),
+            permission,
+            allowance: None,
+            receiver_id: None,
+            method_names: None,
+            nonce: None,
+            block_hash: None,
+            block_timestamp: None,
+            is_final: true,
+            action_index: 0,
+        }
+    }
+
+    fn account_key(key_seed: &str, permission: AccessKeyPermission) -> CurrentAccessKey {
+        CurrentAccessKey {
+            account_id: ACCOUNT_ID.to_string(),
+            public_key: test_utils::public_key(key_seed).to_string(),
+            permission,
+            allowance: None,
+            receiver_id: None,
+            method_names: None,
+            receipt_hash: "earlier-receipt".to_string(),
+            block_height: BigDecimal::from(1),
+            nonce: None,
+            is_deleted: false,
+            action_index: 0,
+        }
+    }
+
+    /// Alerts of `AccountTakeover` on the receipt adding FULL_ACCESS `new` and deleting `deleted`
+    fn takeover_alerts(keys_before: &[CurrentAccessKey], deleted: &str, new: &str) -> Vec<Alert> {
+        let actions = vec![
+            action(AccessKeyAction::Add, new),
+            action(AccessKeyAction::Delete, deleted),
+        ];
+        let new_public_key = test_utils::public_key(new).to_string();
+        let deleted_public_key = test_utils::public_key(deleted).to_string();
+        let keys_after: Vec<CurrentAccessKey> = keys_before
+            .iter()
+            .filter(|account_key| account_key.public_key != deleted_public_key)
+            .cloned()
+            .chain(std::iter::once(account_key(
+                new,
+                AccessKeyPermission::FullAccess,
+            )))
+            .collect();
+        let receipt = ReceiptActions {
+            account_id: ACCOUNT_ID,
+            receipt_hash: RECEIPT_HASH,
+            actions: actions.iter().collect(),
+            keys_before: keys_before.iter().collect(),
+            account_keys: keys_after.iter().collect(),
+        };
+        let alerts = AccountTakeover.evaluate(&receipt);
+        assert!(alerts
+            .iter()
+            .all(|alert| alert.public_key == new_public_key));
+        alerts
+    }
+
+    /// Alerts of `FullAccessKeyOnCrowdedAccount` on the receipt adding FULL_ACCESS `new`
+    /// to the account with `existing_keys` keys, `new` may be one of them
+    fn crowded_account_alerts(
+        max_existing_keys: usize,
+        existing_keys: &[&str],
+        new: &str,
+    ) -> Vec<Alert> {
+        let actions = vec![action(AccessKeyAction::Add, new)];
+        let keys_before: Vec<CurrentAccessKey> = existing_keys
+            .iter()
+            .map(|key_seed| account_key(key_seed, AccessKeyPermission::FunctionCall))
+            .collect();
+        let new_public_key = test_utils::public_key(new).to_string();
+        let keys_after: Vec<CurrentAccessKey> = keys_before
+            .iter()
+            .filter(|account_key| account_key.public_key != new_public_key)
+            .cloned()
+            .chain(std::iter::once(account_key(
+                new,
+                AccessKeyPermission::FullAccess,
+            )))
+            .collect();
+        let receipt = ReceiptActions {
+            account_id: ACCOUNT_ID,
+            receipt_hash: RECEIPT_HASH,
+            actions: actions.iter().collect(),
+            keys_before: keys_before.iter().collect(),
+            account_keys: keys_after.iter().collect(),
+        };
+        FullAccessKeyOnCrowdedAccount { max_existing_keys }.evaluate(&receipt)
+    }
+
+    #[test]
+    fn crowded_account_is_raised_above_the_threshold() {
+        let alerts = crowded_account_alerts(2, &["app-1", "app-2", "app-3"], "new");
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(
+            alerts[0].public_key,
+            test_utils::public_key("new").to_string()
+        );
+    }
+
+    #[test]
+    fn crowded_account_is_not_raised_at_the_threshold() {
+        assert!(crowded_account_alerts(2, &["app-1", "app-2"], "new").is_empty());
+    }
+
+    #[test]
+    fn crowded_account_does_not_count_the_added_key() {
+        assert!(crowded_account_alerts(2, &["app-1", "app-2", "new"], "new").is_empty());
+    }
+
+    #[test]
+    fn takeover_is_raised_when_other_full_access_keys_are_deleted() {
+        let keys_before = vec![
+            account_key("owner", AccessKeyPermission::FullAccess),
+            account_key("app", AccessKeyPermission::FunctionCall),
+        ];
+        assert_eq!(takeover_alerts(&keys_before, "owner", "attacker").len(), 1);
+    }
+
+    #[test]
+    fn takeover_is_not_raised_when_function_call_keys_are_deleted() {
+        let keys_before = vec![
+            account_key("app", AccessKeyPermission::FunctionCall),
+            account_key("other-app", AccessKeyPermission::FunctionCall),
+        ];
+        assert!(takeover_alerts(&keys_before, "app", "owner").is_empty());
+    }
+
+    #[test]
+    fn takeover_is_not_raised_when_other_full_access_keys_are_left() {
+        let keys_before = vec![
+            account_key("owner", AccessKeyPermission::FullAccess),
+            account_key("backup", AccessKeyPermission::FullAccess),
+        ];
+        assert!(takeover_alerts(&keys_before, "owner", "new-owner").is_empty());
+    }
+}
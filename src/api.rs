@@ -0,0 +1,118 @@
+use actix_diesel::dsl::AsyncRunQueryDsl;
+use actix_web::{get, web, App, HttpResponse, HttpServer};
+use diesel::{ExpressionMethods, PgConnection, QueryDsl};
+use itertools::Itertools;
+use tracing::{error, info};
+
+use crate::db::{establish_connection, AccessKey, AccessKeyUsage, CurrentAccessKey};
+use crate::schema;
+use crate::INDEXER_FOR_WALLET;
+
+type Pool = web::Data<actix_diesel::Database<PgConnection>>;
+
+fn internal_error(err: impl std::fmt::Debug) -> actix_web::Error {
+    error!(target: INDEXER_FOR_WALLET, "Failed to query access keys: {:?}", err);
+    actix_web::error::ErrorInternalServerError("Failed to query access keys")
+}
+
+/// Accounts the public key is currently added to
+#[get("/public-key/{public_key}/accounts")]
+async fn accounts_by_public_key(
+    pool: Pool,
+    public_key: web::Path<String>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let access_keys: Vec<CurrentAccessKey> = schema::current_access_keys::table
+        .filter(schema::current_access_keys::dsl::public_key.eq(public_key.into_inner()))
+        .filter(schema::current_access_keys::dsl::is_deleted.eq(false))
+        .load_async(pool.get_ref())
+        .await
+        .map_err(internal_error)?;
+    Ok(HttpResponse::Ok().json(access_keys))
+}
+
+/// Access keys currently present on the account
+#[get("/account/{account_id}/keys")]
+async fn keys_by_account_id(
+    pool: Pool,
+    account_id: web::Path<String>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let access_keys: Vec<CurrentAccessKey> = schema::current_access_keys::table
+        .filter(schema::current_access_keys::dsl::account_id.eq(account_id.into_inner()))
+        .filter(schema::current_access_keys::dsl::is_deleted.eq(false))
+        .load_async(pool.get_ref())
+        .await
+        .map_err(internal_error)?;
+    Ok(HttpResponse::Ok().json(access_keys))
+}
+
+/// Amount of the latest transactions returned by the key usage endpoint
+const KEY_USAGE_LIMIT: i64 = 100;
+
+/// The latest transactions signed by the access key of the account
+#[get("/account/{account_id}/keys/{public_key}/usage")]
+async fn key_usage(
+    pool: Pool,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (account_id, public_key) = path.into_inner();
+    let key_usage: Vec<AccessKeyUsage> = schema::access_key_usage::table
+        .filter(schema::access_key_usage::dsl::account_id.eq(account_id))
+        .filter(schema::access_key_usage::dsl::public_key.eq(public_key))
+        .order(schema::access_key_usage::dsl::block_height.desc())
+        .limit(KEY_USAGE_LIMIT)
+        .load_async(pool.get_ref())
+        .await
+        .map_err(internal_error)?;
+    Ok(HttpResponse::Ok().json(key_usage))
+}
+
+/// All the access key actions of the receipt regardless of their status. The receipt may be
+/// included in the blocks of different forks, the final (or the latest) block wins
+#[get("/receipt/{receipt_hash}")]
+async fn access_keys_by_receipt_hash(
+    pool: Pool,
+    receipt_hash: web::Path<String>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let access_keys: Vec<AccessKey> = schema::access_keys::table
+        .filter(schema::access_keys::dsl::receipt_hash.eq(receipt_hash.into_inner()))
+        .order((
+            schema::access_keys::dsl::is_final.desc(),
+            schema::access_keys::dsl::block_height.desc(),
+        ))
+        .load_async::<AccessKey>(pool.get_ref())
+        .await
+        .map_err(internal_error)?
+        .into_iter()
+        .unique_by(|access_key| {
+            (
+                access_key.account_id.clone(),
+                access_key.public_key.clone(),
+                access_key.action.clone(),
+            )
+        })
+        .collect();
+    if access_keys.is_empty() {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    Ok(HttpResponse::Ok().json(access_keys))
+}
+
+/// Starts read-only HTTP API on top of `current_access_keys` and `access_keys` tables
+pub(crate) async fn serve(bind: &str) -> std::io::Result<()> {
+    let pool: Pool = web::Data::new(establish_connection());
+    info!(
+        target: INDEXER_FOR_WALLET,
+        "NEAR Indexer for Wallet API is listening on {}", bind
+    );
+    HttpServer::new(move || {
+        App::new()
+            .app_data(pool.clone())
+            .service(accounts_by_public_key)
+            .service(keys_by_account_id)
+            .service(key_usage)
+            .service(access_keys_by_receipt_hash)
+    })
+    .bind(bind)?
+    .run()
+    .await
+}
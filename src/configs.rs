@@ -1,5 +1,8 @@
 use clap::Clap;
 
+use crate::dump_file::DumpFormat;
+use crate::state_viewer::LoadTrieMode;
+
 /// NEAR Indexer Example
 /// Watches for stream of blocks from the chain
 #[derive(Clap, Debug)]
@@ -15,11 +18,135 @@ pub(crate) struct Opts {
 #[derive(Clap, Debug)]
 pub(crate) enum SubCommand {
     /// Run NEAR Indexer Example. Start observe the network
-    Run,
+    Run(RunArgs),
     /// Initialize necessary configs
     Init(InitConfigArgs),
-    /// Grab all the Accounts' AccessKeys from the current state to database (warning: replacing data in DB)
-    DumpState,
+    /// Grab all the Accounts' AccessKeys from the current state to database
+    DumpState(DumpStateArgs),
+    /// Import the AccessKeys from the file written by `dump-state --output` to database
+    LoadDump(LoadDumpArgs),
+    /// Compare the AccessKeys in database with the state and report the differences
+    Verify(VerifyArgs),
+    /// Serve read-only HTTP API on top of the indexed AccessKeys
+    Serve(ServeArgs),
+}
+
+#[derive(Clap, Debug)]
+pub(crate) struct DumpStateArgs {
+    /// Replace all the data in DB with the dumped access keys (warning: the history is lost).
+    /// By default only the discrepancies with the known state are recorded
+    #[clap(long)]
+    pub replace: bool,
+    #[clap(flatten)]
+    pub state: StateArgs,
+    /// Write the dumped access keys to the file instead of the database
+    #[clap(long, conflicts_with = "replace")]
+    pub output: Option<std::path::PathBuf>,
+    /// Format of the output file (jsonl, csv)
+    #[clap(long, default_value = "jsonl")]
+    pub format: DumpFormat,
+}
+
+/// Which state to read and how
+#[derive(Clap, Debug)]
+pub(crate) struct StateArgs {
+    /// Read the state at the specified block height instead of the latest one
+    #[clap(long, conflicts_with = "last-final-from-height")]
+    pub height: Option<u64>,
+    /// Read the state at the first final block with height at least the specified one
+    #[clap(long)]
+    pub last_final_from_height: Option<u64>,
+    /// Walk the whole trie instead of seeking to the access keys (slow, useful for comparison)
+    #[clap(long)]
+    pub full_trie_scan: bool,
+    /// Amount of shards read in parallel
+    #[clap(long, default_value = "4")]
+    pub jobs: usize,
+}
+
+impl StateArgs {
+    pub fn load_trie_mode(&self) -> LoadTrieMode {
+        if let Some(height) = self.height {
+            LoadTrieMode::Height(height)
+        } else if let Some(height) = self.last_final_from_height {
+            LoadTrieMode::LastFinalFromHeight(height)
+        } else {
+            LoadTrieMode::Latest
+        }
+    }
+}
+
+#[derive(Clap, Debug)]
+pub(crate) struct VerifyArgs {
+    #[clap(flatten)]
+    pub state: StateArgs,
+    /// Write the full report to the file as JSON
+    #[clap(long)]
+    pub output: Option<std::path::PathBuf>,
+    /// Exit with non-zero code if the database differs from the state
+    #[clap(long)]
+    pub fail_on_drift: bool,
+}
+
+#[derive(Clap, Debug)]
+pub(crate) struct LoadDumpArgs {
+    /// Path to the file written by `dump-state --output`
+    pub path: std::path::PathBuf,
+    /// Replace all the access keys in DB with the dumped ones (warning: the history is lost).
+    /// By default only the discrepancies with the known state are recorded
+    #[clap(long)]
+    pub replace: bool,
+    /// Format of the file (jsonl, csv)
+    #[clap(long, default_value = "jsonl")]
+    pub format: DumpFormat,
+}
+
+#[derive(Clap, Debug)]
+pub(crate) struct ServeArgs {
+    /// Address to bind HTTP server to
+    #[clap(long, default_value = "127.0.0.1:8080")]
+    pub bind: String,
+}
+
+#[derive(Clap, Debug)]
+pub(crate) struct RunArgs {
+    /// Handle blocks strictly one by one in the order of their heights.
+    /// Slower, but doesn't leave PENDING rows caused by races between blocks
+    #[clap(long)]
+    pub ordered: bool,
+    /// Periodically report the receipts which are still PENDING after this amount of blocks
+    #[clap(long, default_value = "1000")]
+    pub stale_pending_blocks: u64,
+    /// Keep the outcomes of the receipts which are not stored yet for this amount of blocks
+    #[clap(long, default_value = "10000")]
+    pub pending_outcomes_retention_blocks: u64,
+    /// Start indexing from the specified block height
+    #[clap(long, conflicts_with_all = &["sync-from-latest", "sync-from-interruption"])]
+    pub sync_from_block: Option<u64>,
+    /// Start indexing from the latest block known to the node
+    #[clap(long, conflicts_with = "sync-from-interruption")]
+    pub sync_from_latest: bool,
+    /// Resume indexing from the node's interruption point ignoring the checkpoints.
+    /// By default the indexer resumes from the last checkpoint and falls back to the
+    /// node's interruption point if there are no checkpoints
+    #[clap(long)]
+    pub sync_from_interruption: bool,
+    /// Stream blocks while the node is syncing instead of waiting for the full sync
+    #[clap(long)]
+    pub stream_while_syncing: bool,
+    /// Keep the history of the access key usage for this amount of blocks (forever by default)
+    #[clap(long)]
+    pub key_usage_retention_blocks: Option<u64>,
+    /// Send `pg_notify` with JSON payload to the channel on every access key change
+    #[clap(long)]
+    pub notify_channel: Option<String>,
+    /// Raise a security alert when a FULL_ACCESS key is added to the account
+    /// with more than this amount of other keys
+    #[clap(long, default_value = "5")]
+    pub alert_max_existing_keys: usize,
+    /// Address to expose Prometheus metrics on (`/metrics`), not exposed by default
+    #[clap(long)]
+    pub metrics_addr: Option<String>,
 }
 
 #[derive(Clap, Debug)]
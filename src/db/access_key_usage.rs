@@ -0,0 +1,33 @@
+use bigdecimal::BigDecimal;
+use serde::{Deserialize, Serialize};
+
+use near_indexer::near_primitives;
+
+use crate::schema;
+use schema::access_key_usage;
+
+/// Transaction signed by the access key
+#[derive(Insertable, Queryable, Clone, Debug, Serialize, Deserialize)]
+#[table_name = "access_key_usage"]
+pub(crate) struct AccessKeyUsage {
+    pub transaction_hash: String,
+    pub account_id: String,
+    pub public_key: String,
+    pub receiver_id: String,
+    pub block_height: BigDecimal,
+}
+
+impl AccessKeyUsage {
+    pub fn from_transaction_view(
+        transaction: &near_primitives::views::SignedTransactionView,
+        block_height: near_primitives::types::BlockHeight,
+    ) -> Self {
+        Self {
+            transaction_hash: transaction.hash.to_string(),
+            account_id: transaction.signer_id.to_string(),
+            public_key: transaction.public_key.to_string(),
+            receiver_id: transaction.receiver_id.to_string(),
+            block_height: block_height.into(),
+        }
+    }
+}
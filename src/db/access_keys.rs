@@ -1,11 +1,18 @@
-use std::convert::TryFrom;
+use std::str::FromStr;
 
-use crate::db::enums::{AccessKeyAction, AccessKeyPermission, ExecutionStatus};
-use crate::schema;
 use bigdecimal::BigDecimal;
+use diesel::{ExpressionMethods, QueryDsl};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use near_indexer::near_primitives;
+
+use crate::db::enums::{AccessKeyAction, AccessKeyPermission, ExecutionStatus};
+use crate::{implicit_accounts, schema};
 use schema::access_keys;
 
-#[derive(Insertable, Queryable, Clone, Debug)]
+#[derive(Insertable, Queryable, QueryableByName, Clone, Debug, Serialize, Deserialize)]
+#[table_name = "access_keys"]
 pub(crate) struct AccessKey {
     pub public_key: String,
     pub account_id: String,
@@ -14,68 +21,216 @@ pub(crate) struct AccessKey {
     pub receipt_hash: String,
     pub block_height: BigDecimal,
     pub permission: AccessKeyPermission,
+    pub allowance: Option<BigDecimal>,
+    pub receiver_id: Option<String>,
+    pub method_names: Option<Vec<String>>,
+    pub nonce: Option<BigDecimal>,
+    pub block_hash: Option<String>,
+    pub block_timestamp: Option<BigDecimal>,
+    pub is_final: bool,
+    /// Position of the action in its receipt, orders the actions of the same block.
+    /// The dump files written before it was introduced don't have it
+    #[serde(default)]
+    pub action_index: i32,
+}
+
+/// Details of the `FunctionCall` permission. All the fields are `None` for `FullAccess`
+#[derive(Clone, Debug, Default)]
+pub(crate) struct PermissionDetails {
+    pub allowance: Option<BigDecimal>,
+    pub receiver_id: Option<String>,
+    pub method_names: Option<Vec<String>>,
+}
+
+impl PermissionDetails {
+    fn function_call(
+        allowance: Option<near_primitives::types::Balance>,
+        receiver_id: &str,
+        method_names: &[String],
+    ) -> Self {
+        Self {
+            allowance: allowance.map(|allowance| {
+                BigDecimal::from_str(&allowance.to_string())
+                    .expect("Balance is always a valid decimal")
+            }),
+            receiver_id: Some(receiver_id.to_string()),
+            method_names: Some(method_names.to_vec()),
+        }
+    }
+}
+
+impl From<&near_primitives::views::AccessKeyPermissionView> for PermissionDetails {
+    fn from(item: &near_primitives::views::AccessKeyPermissionView) -> Self {
+        match item {
+            near_primitives::views::AccessKeyPermissionView::FunctionCall {
+                allowance,
+                receiver_id,
+                method_names,
+            } => Self::function_call(*allowance, receiver_id, method_names),
+            near_primitives::views::AccessKeyPermissionView::FullAccess => Self::default(),
+        }
+    }
+}
+
+impl From<&near_primitives::account::AccessKeyPermission> for PermissionDetails {
+    fn from(item: &near_primitives::account::AccessKeyPermission) -> Self {
+        match item {
+            near_primitives::account::AccessKeyPermission::FunctionCall(permission) => {
+                Self::function_call(
+                    permission.allowance,
+                    &permission.receiver_id,
+                    &permission.method_names,
+                )
+            }
+            near_primitives::account::AccessKeyPermission::FullAccess => Self::default(),
+        }
+    }
+}
+
+/// Query of the latest successful action for every (`account_id`, `public_key`) pair.
+/// The key is present if the action is `ADD` ("last successful action wins"). The actions of
+/// the same block are ordered by their position in the receipt
+/// Note that filtering by `action` must be done on the loaded rows, not in the query.
+pub(crate) fn last_successful_actions() -> access_keys::BoxedQuery<'static, diesel::pg::Pg> {
+    access_keys::table
+        .filter(access_keys::dsl::status.eq(ExecutionStatus::Success))
+        .distinct_on((access_keys::dsl::account_id, access_keys::dsl::public_key))
+        .order((
+            access_keys::dsl::account_id,
+            access_keys::dsl::public_key,
+            access_keys::dsl::block_height.desc(),
+            access_keys::dsl::action_index.desc(),
+        ))
+        .into_boxed()
+}
+
+/// Whether the receipt deletes its receiver account
+pub(crate) fn is_delete_account_receipt(
+    receipt: &near_indexer::near_primitives::views::ReceiptView,
+) -> bool {
+    match &receipt.receipt {
+        near_indexer::near_primitives::views::ReceiptEnumView::Action { actions, .. } => {
+            actions.iter().any(|action| {
+                matches!(
+                    action,
+                    near_indexer::near_primitives::views::ActionView::DeleteAccount { .. }
+                )
+            })
+        }
+        _ => false,
+    }
 }
 
 impl AccessKey {
+    /// `account_keys` are the public keys known to be present on the receiver account before
+    /// the receipt, they are needed only to record `DeleteAccount` as deletion of all the keys
     pub fn from_receipt_view(
         receipt: &near_indexer::near_primitives::views::ReceiptView,
-        block_height: u64,
+        block: &near_indexer::near_primitives::views::BlockHeaderView,
         status: Option<ExecutionStatus>,
+        account_keys: &[String],
     ) -> Vec<Self> {
         let mut access_keys: Vec<Self> = vec![];
+        // Keys present on the account at the current action of the receipt
+        let mut live_keys: Vec<String> = account_keys.to_vec();
         if let near_indexer::near_primitives::views::ReceiptEnumView::Action { actions, .. } =
             &receipt.receipt
         {
-            for action in actions {
+            for (action_index, action) in actions.iter().enumerate() {
+                let action_index = action_index as i32;
                 let access_key = match action {
                     near_indexer::near_primitives::views::ActionView::AddKey {
                         public_key,
                         access_key,
-                    } => Self {
-                        public_key: public_key.to_string(),
-                        account_id: receipt.receiver_id.to_string(),
-                        action: AccessKeyAction::Add,
-                        status: status.unwrap_or_else(|| ExecutionStatus::Pending),
-                        receipt_hash: receipt.receipt_id.to_string(),
-                        block_height: block_height.into(),
-                        permission: (&access_key.permission).into(),
-                    },
+                    } => {
+                        live_keys.push(public_key.to_string());
+                        let details = PermissionDetails::from(&access_key.permission);
+                        Self {
+                            public_key: public_key.to_string(),
+                            account_id: receipt.receiver_id.to_string(),
+                            action: AccessKeyAction::Add,
+                            status: status.unwrap_or_else(|| ExecutionStatus::Pending),
+                            receipt_hash: receipt.receipt_id.to_string(),
+                            block_height: block.height.into(),
+                            permission: (&access_key.permission).into(),
+                            allowance: details.allowance,
+                            receiver_id: details.receiver_id,
+                            method_names: details.method_names,
+                            nonce: Some(access_key.nonce.into()),
+                            block_hash: Some(block.hash.to_string()),
+                            block_timestamp: Some(block.timestamp.into()),
+                            is_final: false,
+                            action_index,
+                        }
+                    }
                     near_indexer::near_primitives::views::ActionView::DeleteKey { public_key } => {
+                        live_keys.retain(|live_key| live_key != &public_key.to_string());
                         Self {
                             public_key: public_key.to_string(),
                             account_id: receipt.receiver_id.to_string(),
                             action: AccessKeyAction::Delete,
                             status: status.unwrap_or_else(|| ExecutionStatus::Pending),
                             receipt_hash: receipt.receipt_id.to_string(),
-                            block_height: block_height.into(),
+                            block_height: block.height.into(),
                             permission: AccessKeyPermission::NotApplicable,
+                            allowance: None,
+                            receiver_id: None,
+                            method_names: None,
+                            nonce: None,
+                            block_hash: Some(block.hash.to_string()),
+                            block_timestamp: Some(block.timestamp.into()),
+                            is_final: false,
+                            action_index,
                         }
                     }
                     near_indexer::near_primitives::views::ActionView::Transfer { .. } => {
-                        if receipt.receiver_id.len() != 64usize {
-                            continue;
-                        }
-                        if let Ok(public_key_bytes) = hex::decode(&receipt.receiver_id) {
-                            if let Ok(public_key) =
-                                near_crypto::ED25519PublicKey::try_from(&public_key_bytes[..])
-                            {
-                                Self {
-                                    public_key: near_crypto::PublicKey::from(public_key)
-                                        .to_string(),
-                                    account_id: receipt.receiver_id.to_string(),
-                                    action: AccessKeyAction::Add,
-                                    status: status.unwrap_or_else(|| ExecutionStatus::Pending),
-                                    receipt_hash: receipt.receipt_id.to_string(),
-                                    block_height: block_height.into(),
-                                    permission: AccessKeyPermission::FullAccess,
-                                }
-                            } else {
-                                continue;
-                            }
-                        } else {
-                            continue;
+                        let public_key = match implicit_accounts::implicit_account_public_key(
+                            &receipt.receiver_id,
+                        ) {
+                            Some(public_key) => public_key.to_string(),
+                            None => continue,
+                        };
+                        live_keys.push(public_key.clone());
+                        Self {
+                            public_key,
+                            account_id: receipt.receiver_id.to_string(),
+                            action: AccessKeyAction::Add,
+                            status: status.unwrap_or_else(|| ExecutionStatus::Pending),
+                            receipt_hash: receipt.receipt_id.to_string(),
+                            block_height: block.height.into(),
+                            permission: AccessKeyPermission::FullAccess,
+                            allowance: None,
+                            receiver_id: None,
+                            method_names: None,
+                            // Implicit account is created with a fresh key
+                            nonce: Some(0.into()),
+                            block_hash: Some(block.hash.to_string()),
+                            block_timestamp: Some(block.timestamp.into()),
+                            is_final: false,
+                            action_index,
                         }
                     }
+                    near_indexer::near_primitives::views::ActionView::DeleteAccount { .. } => {
+                        // All the keys are gone along with the account
+                        access_keys.extend(live_keys.drain(..).unique().map(|public_key| Self {
+                            public_key,
+                            account_id: receipt.receiver_id.to_string(),
+                            action: AccessKeyAction::Delete,
+                            status: status.unwrap_or_else(|| ExecutionStatus::Pending),
+                            receipt_hash: receipt.receipt_id.to_string(),
+                            block_height: block.height.into(),
+                            permission: AccessKeyPermission::NotApplicable,
+                            allowance: None,
+                            receiver_id: None,
+                            method_names: None,
+                            nonce: None,
+                            block_hash: Some(block.hash.to_string()),
+                            block_timestamp: Some(block.timestamp.into()),
+                            is_final: false,
+                            action_index,
+                        }));
+                        continue;
+                    }
                     _ => continue,
                 };
                 access_keys.push(access_key);
@@ -84,3 +239,121 @@ impl AccessKey {
         access_keys
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+
+    const ACCOUNT_ID: &str = "alice.near";
+
+    fn block() -> near_indexer::near_primitives::views::BlockHeaderView {
+        test_utils::block_header(
+            "block",
+            10,
+            test_utils::crypto_hash("prev"),
+            test_utils::crypto_hash("prev"),
+        )
+    }
+
+    fn actions(access_keys: &[AccessKey]) -> Vec<(AccessKeyAction, String)> {
+        access_keys
+            .iter()
+            .map(|access_key| (access_key.action.clone(), access_key.public_key.clone()))
+            .collect()
+    }
+
+    fn public_key(seed: &str) -> String {
+        test_utils::public_key(seed).to_string()
+    }
+
+    #[test]
+    fn delete_account_deletes_existing_keys() {
+        let receipt =
+            test_utils::action_receipt("receipt", ACCOUNT_ID, vec![test_utils::delete_account()]);
+        let access_keys = AccessKey::from_receipt_view(
+            &receipt,
+            &block(),
+            Some(ExecutionStatus::Success),
+            &[public_key("a"), public_key("b")],
+        );
+        assert_eq!(
+            actions(&access_keys),
+            vec![
+                (AccessKeyAction::Delete, public_key("a")),
+                (AccessKeyAction::Delete, public_key("b")),
+            ]
+        );
+        assert!(access_keys
+            .iter()
+            .all(|access_key| access_key.account_id == ACCOUNT_ID
+                && matches!(access_key.permission, AccessKeyPermission::NotApplicable)));
+    }
+
+    #[test]
+    fn delete_account_deletes_keys_added_by_the_same_receipt() {
+        let receipt = test_utils::action_receipt(
+            "receipt",
+            ACCOUNT_ID,
+            vec![
+                test_utils::add_full_access_key("b"),
+                test_utils::delete_account(),
+            ],
+        );
+        let access_keys = AccessKey::from_receipt_view(
+            &receipt,
+            &block(),
+            Some(ExecutionStatus::Success),
+            &[public_key("a")],
+        );
+        assert_eq!(
+            actions(&access_keys),
+            vec![
+                (AccessKeyAction::Add, public_key("b")),
+                (AccessKeyAction::Delete, public_key("a")),
+                (AccessKeyAction::Delete, public_key("b")),
+            ]
+        );
+    }
+
+    #[test]
+    fn delete_account_skips_keys_deleted_by_the_same_receipt() {
+        let receipt = test_utils::action_receipt(
+            "receipt",
+            ACCOUNT_ID,
+            vec![test_utils::delete_key("a"), test_utils::delete_account()],
+        );
+        let access_keys = AccessKey::from_receipt_view(
+            &receipt,
+            &block(),
+            Some(ExecutionStatus::Success),
+            &[public_key("a"), public_key("b")],
+        );
+        assert_eq!(
+            actions(&access_keys),
+            vec![
+                (AccessKeyAction::Delete, public_key("a")),
+                (AccessKeyAction::Delete, public_key("b")),
+            ]
+        );
+    }
+
+    #[test]
+    fn status_is_pending_until_outcome_is_known() {
+        let receipt = test_utils::action_receipt(
+            "receipt",
+            ACCOUNT_ID,
+            vec![test_utils::add_full_access_key("a")],
+        );
+        let pending = AccessKey::from_receipt_view(&receipt, &block(), None, &[]);
+        assert!(matches!(pending[0].status, ExecutionStatus::Pending));
+
+        let succeeded =
+            AccessKey::from_receipt_view(&receipt, &block(), Some(ExecutionStatus::Success), &[]);
+        assert!(matches!(succeeded[0].status, ExecutionStatus::Success));
+        assert_eq!(succeeded[0].receipt_hash, receipt.receipt_id.to_string());
+        assert_eq!(succeeded[0].block_height, BigDecimal::from(10));
+        assert_eq!(succeeded[0].block_hash, Some(block().hash.to_string()));
+        assert!(!succeeded[0].is_final);
+    }
+}
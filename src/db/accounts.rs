@@ -0,0 +1,77 @@
+use bigdecimal::BigDecimal;
+use serde::{Deserialize, Serialize};
+
+use near_indexer::near_primitives;
+
+use crate::{implicit_accounts, schema};
+use schema::accounts;
+
+#[derive(Insertable, Queryable, Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct Account {
+    pub account_id: String,
+    pub is_implicit: bool,
+    pub created_by_receipt_hash: Option<String>,
+    pub created_by_account_id: Option<String>,
+    pub created_at_block_height: Option<BigDecimal>,
+    pub deleted_by_receipt_hash: Option<String>,
+    pub deleted_at_block_height: Option<BigDecimal>,
+}
+
+impl Account {
+    /// Account which exists in the state, its creation is unknown
+    pub fn from_state(account_id: &str) -> Self {
+        Self {
+            account_id: account_id.to_string(),
+            is_implicit: implicit_accounts::is_implicit_account_id(account_id),
+            created_by_receipt_hash: None,
+            created_by_account_id: None,
+            created_at_block_height: None,
+            deleted_by_receipt_hash: None,
+            deleted_at_block_height: None,
+        }
+    }
+
+    /// Account created by the successful receipt either explicitly with `CreateAccount` action
+    /// or implicitly with `Transfer` to the implicit account id. Note that the transfers to
+    /// already existing implicit accounts are not distinguished here
+    pub fn created_from_receipt_view(
+        receipt: &near_primitives::views::ReceiptView,
+        block_height: near_primitives::types::BlockHeight,
+    ) -> Option<Self> {
+        let actions = match &receipt.receipt {
+            near_primitives::views::ReceiptEnumView::Action { actions, .. } => actions,
+            _ => return None,
+        };
+        let is_created_explicitly = actions.iter().any(|action| {
+            matches!(action, near_primitives::views::ActionView::CreateAccount)
+        });
+        let is_created_implicitly = !is_created_explicitly
+            && implicit_accounts::is_implicit_account_id(&receipt.receiver_id)
+            && actions.iter().any(|action| {
+                matches!(action, near_primitives::views::ActionView::Transfer { .. })
+            });
+        if !is_created_explicitly && !is_created_implicitly {
+            return None;
+        }
+        Some(Self {
+            account_id: receipt.receiver_id.to_string(),
+            is_implicit: implicit_accounts::is_implicit_account_id(&receipt.receiver_id),
+            created_by_receipt_hash: Some(receipt.receipt_id.to_string()),
+            created_by_account_id: Some(receipt.predecessor_id.to_string()),
+            created_at_block_height: Some(block_height.into()),
+            deleted_by_receipt_hash: None,
+            deleted_at_block_height: None,
+        })
+    }
+}
+
+/// Change of the account made by the successful receipt
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) enum AccountChange {
+    Created(Account),
+    Deleted {
+        account_id: String,
+        receipt_hash: String,
+        block_height: BigDecimal,
+    },
+}
@@ -0,0 +1,32 @@
+use bigdecimal::BigDecimal;
+use serde::Serialize;
+
+use crate::db::AccessKey;
+use crate::schema;
+use schema::alerts;
+
+/// Security alert raised by the rule for the access key action
+#[derive(Insertable, Queryable, Clone, Debug, Serialize)]
+pub(crate) struct Alert {
+    pub rule: String,
+    pub account_id: String,
+    pub public_key: String,
+    pub receipt_hash: String,
+    pub block_height: BigDecimal,
+    pub block_timestamp: Option<BigDecimal>,
+    pub message: String,
+}
+
+impl Alert {
+    pub fn new(rule: &str, access_key: &AccessKey, message: String) -> Self {
+        Self {
+            rule: rule.to_string(),
+            account_id: access_key.account_id.clone(),
+            public_key: access_key.public_key.clone(),
+            receipt_hash: access_key.receipt_hash.clone(),
+            block_height: access_key.block_height.clone(),
+            block_timestamp: access_key.block_timestamp.clone(),
+            message,
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use bigdecimal::BigDecimal;
+use diesel::sql_types::{Array, Bool, Integer, Nullable, Numeric, Text};
+use diesel::{PgConnection, RunQueryDsl};
+use serde::Serialize;
+
+use crate::db::enums::{AccessKeyAction, AccessKeyPermission, Access_key_permission_type};
+use crate::db::AccessKey;
+use crate::schema;
+use schema::current_access_keys;
+
+/// Latest successful action of the (`account_id`, `public_key`) pair. The key is live if the
+/// action is `ADD`, the deleted keys are kept as tombstones (`is_deleted`), so the older
+/// actions applied out of order can't bring them back
+#[derive(Insertable, Queryable, QueryableByName, Clone, Debug, Serialize)]
+#[table_name = "current_access_keys"]
+pub(crate) struct CurrentAccessKey {
+    pub account_id: String,
+    pub public_key: String,
+    pub permission: AccessKeyPermission,
+    pub allowance: Option<BigDecimal>,
+    pub receiver_id: Option<String>,
+    pub method_names: Option<Vec<String>>,
+    pub receipt_hash: String,
+    pub block_height: BigDecimal,
+    pub nonce: Option<BigDecimal>,
+    pub is_deleted: bool,
+    pub action_index: i32,
+}
+
+impl From<&AccessKey> for CurrentAccessKey {
+    fn from(access_key: &AccessKey) -> Self {
+        Self {
+            account_id: access_key.account_id.clone(),
+            public_key: access_key.public_key.clone(),
+            permission: access_key.permission.clone(),
+            allowance: access_key.allowance.clone(),
+            receiver_id: access_key.receiver_id.clone(),
+            method_names: access_key.method_names.clone(),
+            receipt_hash: access_key.receipt_hash.clone(),
+            block_height: access_key.block_height.clone(),
+            nonce: access_key.nonce.clone(),
+            is_deleted: matches!(access_key.action, AccessKeyAction::Delete),
+            action_index: access_key.action_index,
+        }
+    }
+}
+
+/// Writes the action unless a newer one is already applied to the pair. The actions of the same
+/// block are ordered by `action_index`. The nonce advanced by the transactions is kept if
+/// the same action is applied again
+pub(crate) fn upsert_current_access_key(
+    conn: &PgConnection,
+    current_access_key: &CurrentAccessKey,
+) -> diesel::QueryResult<()> {
+    diesel::sql_query(
+        r#"
+        INSERT INTO current_access_keys (
+            account_id, public_key, "permission", allowance, receiver_id, method_names,
+            receipt_hash, block_height, nonce, is_deleted, action_index
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+        ON CONFLICT (account_id, public_key) DO UPDATE SET
+            "permission" = excluded."permission",
+            allowance = excluded.allowance,
+            receiver_id = excluded.receiver_id,
+            method_names = excluded.method_names,
+            receipt_hash = excluded.receipt_hash,
+            block_height = excluded.block_height,
+            nonce = CASE
+                WHEN current_access_keys.receipt_hash = excluded.receipt_hash
+                    THEN GREATEST(current_access_keys.nonce, excluded.nonce)
+                ELSE excluded.nonce
+            END,
+            is_deleted = excluded.is_deleted,
+            action_index = excluded.action_index
+        WHERE (current_access_keys.block_height, current_access_keys.action_index)
+            <= (excluded.block_height, excluded.action_index)
+        "#,
+    )
+    .bind::<Text, _>(&current_access_key.account_id)
+    .bind::<Text, _>(&current_access_key.public_key)
+    .bind::<Access_key_permission_type, _>(&current_access_key.permission)
+    .bind::<Nullable<Numeric>, _>(&current_access_key.allowance)
+    .bind::<Nullable<Text>, _>(&current_access_key.receiver_id)
+    .bind::<Nullable<Array<Text>>, _>(&current_access_key.method_names)
+    .bind::<Text, _>(&current_access_key.receipt_hash)
+    .bind::<Numeric, _>(&current_access_key.block_height)
+    .bind::<Nullable<Numeric>, _>(&current_access_key.nonce)
+    .bind::<Bool, _>(current_access_key.is_deleted)
+    .bind::<Integer, _>(current_access_key.action_index)
+    .execute(conn)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::{Connection, ExpressionMethods, QueryDsl};
+
+    use super::*;
+    use crate::test_utils;
+
+    fn action(action: AccessKeyAction, block_height: u64, action_index: i32) -> CurrentAccessKey {
+        CurrentAccessKey {
+            account_id: "alice.near".to_string(),
+            public_key: test_utils::public_key("k1").to_string(),
+            permission: match action {
+                AccessKeyAction::Add => AccessKeyPermission::FullAccess,
+                AccessKeyAction::Delete => AccessKeyPermission::NotApplicable,
+            },
+            allowance: None,
+            receiver_id: None,
+            method_names: None,
+            receipt_hash: format!("receipt-{}", block_height),
+            block_height: block_height.into(),
+            nonce: None,
+            is_deleted: matches!(action, AccessKeyAction::Delete),
+            action_index,
+        }
+    }
+
+    fn is_deleted(conn: &PgConnection) -> bool {
+        current_access_keys::table
+            .select(current_access_keys::dsl::is_deleted)
+            .filter(current_access_keys::dsl::account_id.eq("alice.near"))
+            .first(conn)
+            .unwrap()
+    }
+
+    #[test]
+    #[ignore]
+    fn older_actions_are_not_applied_over_newer_ones() {
+        let conn = test_utils::test_connection();
+        conn.begin_test_transaction().unwrap();
+
+        // `AddKey` and `DeleteAccount` of the same receipt applied in reverse order
+        upsert_current_access_key(&conn, &action(AccessKeyAction::Delete, 2, 1)).unwrap();
+        upsert_current_access_key(&conn, &action(AccessKeyAction::Add, 2, 0)).unwrap();
+        assert!(is_deleted(&conn));
+        upsert_current_access_key(&conn, &action(AccessKeyAction::Add, 1, 5)).unwrap();
+        assert!(is_deleted(&conn));
+        upsert_current_access_key(&conn, &action(AccessKeyAction::Add, 3, 0)).unwrap();
+        assert!(!is_deleted(&conn));
+    }
+}
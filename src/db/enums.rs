@@ -1,8 +1,10 @@
 use diesel_derive_enum::DbEnum;
+use serde::{Deserialize, Serialize};
 
 use near_indexer::near_primitives;
 
-#[derive(Debug, DbEnum, Clone)]
+#[derive(Debug, DbEnum, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 #[DbValueStyle = "SCREAMING_SNAKE_CASE"]
 #[DieselType = "Access_key_action_type"]
 #[PgType = "access_key_action_type"]
@@ -11,7 +13,8 @@ pub enum AccessKeyAction {
     Delete,
 }
 
-#[derive(Debug, DbEnum, Clone, Copy)]
+#[derive(Debug, DbEnum, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 #[DbValueStyle = "SCREAMING_SNAKE_CASE"]
 #[DieselType = "Execution_status_type"]
 #[PgType = "execution_status_type"]
@@ -33,7 +36,8 @@ impl From<near_primitives::views::ExecutionStatusView> for ExecutionStatus {
     }
 }
 
-#[derive(Debug, DbEnum, Clone)]
+#[derive(Debug, DbEnum, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 #[DbValueStyle = "SCREAMING_SNAKE_CASE"]
 #[DieselType = "Access_key_permission_type"]
 #[PgType = "access_key_permission_type"]
@@ -65,3 +69,15 @@ impl From<&near_primitives::account::AccessKeyPermission> for AccessKeyPermissio
         }
     }
 }
+
+#[derive(Debug, DbEnum, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+#[DbValueStyle = "SCREAMING_SNAKE_CASE"]
+#[DieselType = "Webhook_delivery_status_type"]
+#[PgType = "webhook_delivery_status_type"]
+pub enum WebhookDeliveryStatus {
+    Pending,
+    Delivered,
+    /// All the delivery attempts failed
+    Failed,
+}
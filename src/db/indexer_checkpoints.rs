@@ -0,0 +1,69 @@
+use bigdecimal::BigDecimal;
+use diesel::sql_types::{BigInt, Nullable};
+use diesel::{PgConnection, RunQueryDsl};
+
+use near_indexer::near_primitives;
+
+use crate::schema;
+use schema::indexer_checkpoints;
+
+/// The checkpoints within this amount of blocks below the latest one are considered
+/// while looking for the place to resume from. It must be greater than the amount
+/// of blocks handled concurrently
+const RESUME_WINDOW: i64 = 1000;
+
+#[derive(Insertable, Queryable, QueryableByName, Clone, Debug)]
+#[table_name = "indexer_checkpoints"]
+pub(crate) struct IndexerCheckpoint {
+    pub block_height: BigDecimal,
+    pub block_hash: String,
+    pub prev_block_hash: String,
+    pub block_timestamp: BigDecimal,
+    pub is_final: bool,
+    /// `last_final_block` of the block header, NULL for the blocks stored before it was recorded
+    pub last_final_block_hash: Option<String>,
+}
+
+impl From<&near_primitives::views::BlockHeaderView> for IndexerCheckpoint {
+    fn from(header: &near_primitives::views::BlockHeaderView) -> Self {
+        Self {
+            block_height: header.height.into(),
+            block_hash: header.hash.to_string(),
+            prev_block_hash: header.prev_hash.to_string(),
+            block_timestamp: header.timestamp.into(),
+            is_final: false,
+            last_final_block_hash: Some(header.last_final_block.to_string()),
+        }
+    }
+}
+
+#[derive(QueryableByName)]
+struct ResumeHeight {
+    #[sql_type = "Nullable<BigInt>"]
+    block_height: Option<i64>,
+}
+
+/// Returns the height of the last block of the contiguous range of processed blocks.
+/// Blocks are handled concurrently, so there might be gaps below the latest checkpoint,
+/// thus we look for the lowest checkpoint which has no checkpoint of the next block
+pub(crate) fn resume_height(
+    conn: &PgConnection,
+) -> diesel::QueryResult<Option<near_primitives::types::BlockHeight>> {
+    let resume_height: ResumeHeight = diesel::sql_query(
+        r#"
+        SELECT MIN(checkpoint.block_height)::bigint AS block_height
+        FROM indexer_checkpoints AS checkpoint
+        WHERE checkpoint.block_height > (SELECT MAX(block_height) FROM indexer_checkpoints) - $1
+            AND NOT EXISTS (
+                SELECT 1
+                FROM indexer_checkpoints AS next_checkpoint
+                WHERE next_checkpoint.prev_block_hash = checkpoint.block_hash
+            )
+        "#,
+    )
+    .bind::<BigInt, _>(RESUME_WINDOW)
+    .get_result(conn)?;
+    Ok(resume_height
+        .block_height
+        .map(|height| height as near_primitives::types::BlockHeight))
+}
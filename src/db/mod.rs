@@ -1,12 +1,34 @@
 use std::env;
 
-use diesel::PgConnection;
+use diesel::{Connection, PgConnection};
 use dotenv::dotenv;
 
+pub(crate) mod access_key_usage;
 pub(crate) mod access_keys;
+pub(crate) mod accounts;
+pub(crate) mod alerts;
+pub(crate) mod current_access_keys;
 pub(crate) mod enums;
+pub(crate) mod indexer_checkpoints;
+pub(crate) mod pending_outcomes;
+pub(crate) mod staged_changes;
+pub(crate) mod state_dump_keys;
+pub(crate) mod state_dumps;
+pub(crate) mod webhook_deliveries;
+pub(crate) mod webhooks;
 
-pub(crate) use access_keys::AccessKey;
+pub(crate) use access_key_usage::AccessKeyUsage;
+pub(crate) use access_keys::{AccessKey, PermissionDetails};
+pub(crate) use accounts::{Account, AccountChange};
+pub(crate) use alerts::Alert;
+pub(crate) use current_access_keys::CurrentAccessKey;
+pub(crate) use indexer_checkpoints::IndexerCheckpoint;
+pub(crate) use pending_outcomes::PendingOutcome;
+pub(crate) use staged_changes::{BlockChanges, KeyNonce, ReceiptOutcome, StagedChanges};
+pub(crate) use state_dump_keys::StateDumpKey;
+pub(crate) use state_dumps::StateDump;
+pub(crate) use webhook_deliveries::{NewWebhookDelivery, WebhookDelivery};
+pub(crate) use webhooks::Webhook;
 
 pub(crate) fn establish_connection() -> actix_diesel::Database<PgConnection> {
     dotenv().ok();
@@ -15,3 +37,14 @@ pub(crate) fn establish_connection() -> actix_diesel::Database<PgConnection> {
         .unwrap_or_else(|_| panic!("DATABASE_URL must be set in .env file"));
     actix_diesel::Database::builder().open(&database_url)
 }
+
+/// Connection outside of the pool for the reads which must stay on the same connection,
+/// e.g. with a cursor
+pub(crate) fn establish_single_connection() -> Result<PgConnection, String> {
+    dotenv().ok();
+
+    let database_url = env::var("DATABASE_URL")
+        .map_err(|_| "DATABASE_URL must be set in .env file".to_string())?;
+    PgConnection::establish(&database_url)
+        .map_err(|err| format!("Failed to connect to database: {}", err))
+}
@@ -0,0 +1,13 @@
+use bigdecimal::BigDecimal;
+
+use crate::db::enums::ExecutionStatus;
+use crate::schema;
+use schema::pending_outcomes;
+
+/// Execution outcome which has no related rows in `access_keys` yet
+#[derive(Insertable, Queryable, Clone, Debug)]
+pub(crate) struct PendingOutcome {
+    pub receipt_hash: String,
+    pub status: ExecutionStatus,
+    pub block_height: BigDecimal,
+}
@@ -0,0 +1,69 @@
+use bigdecimal::BigDecimal;
+use diesel::{ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+
+use near_indexer::near_primitives;
+
+use crate::db::enums::ExecutionStatus;
+use crate::db::{AccessKeyUsage, AccountChange};
+use crate::schema;
+use schema::staged_changes;
+
+/// Changes of the block which are applied once the block is final, so the blocks which are
+/// rolled back leave nothing behind but their own `access_keys` rows
+#[derive(Insertable, Queryable, Clone, Debug)]
+#[table_name = "staged_changes"]
+pub(crate) struct StagedChanges {
+    pub block_hash: String,
+    pub block_height: BigDecimal,
+    pub changes: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub(crate) struct BlockChanges {
+    /// Outcomes of the receipts with access key actions
+    pub outcomes: Vec<ReceiptOutcome>,
+    pub accounts: Vec<AccountChange>,
+    /// The highest nonce of the transactions signed by every access key
+    pub nonces: Vec<KeyNonce>,
+    pub key_usage: Vec<AccessKeyUsage>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct ReceiptOutcome {
+    pub receipt_hash: String,
+    pub status: ExecutionStatus,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct KeyNonce {
+    pub account_id: String,
+    pub public_key: String,
+    pub nonce: BigDecimal,
+}
+
+impl StagedChanges {
+    pub fn new(block: &near_primitives::views::BlockHeaderView, changes: &BlockChanges) -> Self {
+        Self {
+            block_hash: block.hash.to_string(),
+            block_height: block.height.into(),
+            changes: serde_json::to_value(changes).expect("Block changes are always serializable"),
+        }
+    }
+}
+
+/// Removes the staged changes of the block and returns them. Blocks stored before the changes
+/// were staged have none
+pub(crate) fn take_staged_changes(
+    conn: &PgConnection,
+    block_hash: &str,
+) -> diesel::QueryResult<Option<BlockChanges>> {
+    let staged_changes: Vec<StagedChanges> = diesel::delete(
+        staged_changes::table.filter(staged_changes::dsl::block_hash.eq(block_hash)),
+    )
+    .get_results(conn)?;
+    Ok(staged_changes.into_iter().next().map(|staged_changes| {
+        serde_json::from_value(staged_changes.changes)
+            .expect("Staged changes are written by the indexer")
+    }))
+}
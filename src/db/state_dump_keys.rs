@@ -0,0 +1,9 @@
+use crate::schema;
+use schema::state_dump_keys;
+
+/// Key met in the state by the merging dump
+#[derive(Insertable, Clone, Debug)]
+pub(crate) struct StateDumpKey {
+    pub account_id: String,
+    pub public_key: String,
+}
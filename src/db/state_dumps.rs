@@ -0,0 +1,23 @@
+use bigdecimal::BigDecimal;
+
+use near_indexer::near_primitives;
+
+use crate::schema;
+use schema::state_dumps;
+
+#[derive(Insertable, Queryable, Clone, Debug)]
+pub(crate) struct StateDump {
+    pub block_height: BigDecimal,
+    pub block_hash: String,
+    pub block_timestamp: BigDecimal,
+}
+
+impl From<&near_primitives::block_header::BlockHeader> for StateDump {
+    fn from(header: &near_primitives::block_header::BlockHeader) -> Self {
+        Self {
+            block_height: header.height().into(),
+            block_hash: header.hash().to_string(),
+            block_timestamp: header.raw_timestamp().into(),
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use crate::db::enums::{AccessKeyAction, WebhookDeliveryStatus};
+use crate::schema;
+use schema::webhook_deliveries;
+
+/// Delivery of the webhook payload, the row is kept as a log once it is delivered or failed
+#[derive(Queryable, Clone, Debug)]
+pub(crate) struct WebhookDelivery {
+    pub id: i64,
+    pub webhook_id: i32,
+    pub receipt_hash: String,
+    pub account_id: String,
+    pub public_key: String,
+    pub action: AccessKeyAction,
+    pub payload: String,
+    pub status: WebhookDeliveryStatus,
+    pub attempts: i32,
+    /// Unix timestamp in milliseconds
+    pub next_attempt_at: i64,
+    pub response_status: Option<i32>,
+    pub last_error: Option<String>,
+    /// Unix timestamp in milliseconds
+    pub delivered_at: Option<i64>,
+}
+
+#[derive(Insertable, Clone, Debug)]
+#[table_name = "webhook_deliveries"]
+pub(crate) struct NewWebhookDelivery {
+    pub webhook_id: i32,
+    pub receipt_hash: String,
+    pub account_id: String,
+    pub public_key: String,
+    pub action: AccessKeyAction,
+    pub payload: String,
+    pub status: WebhookDeliveryStatus,
+    pub attempts: i32,
+    pub next_attempt_at: i64,
+}
@@ -0,0 +1,35 @@
+use crate::db::enums::{AccessKeyAction, AccessKeyPermission};
+use crate::db::AccessKey;
+
+/// Webhook called when the matching access key action becomes SUCCESS.
+/// Every `None` filter matches any value
+#[derive(Queryable, Clone, Debug)]
+pub(crate) struct Webhook {
+    pub id: i32,
+    pub url: String,
+    pub secret: String,
+    pub account_id: Option<String>,
+    pub public_key: Option<String>,
+    pub action: Option<AccessKeyAction>,
+    pub permission: Option<AccessKeyPermission>,
+}
+
+impl Webhook {
+    pub fn matches(&self, access_key: &AccessKey) -> bool {
+        self.account_id
+            .as_ref()
+            .map_or(true, |account_id| account_id == &access_key.account_id)
+            && self
+                .public_key
+                .as_ref()
+                .map_or(true, |public_key| public_key == &access_key.public_key)
+            && self
+                .action
+                .as_ref()
+                .map_or(true, |action| action == &access_key.action)
+            && self
+                .permission
+                .as_ref()
+                .map_or(true, |permission| permission == &access_key.permission)
+    }
+}
@@ -0,0 +1,154 @@
+use std::io::{BufRead, Write};
+
+use bigdecimal::BigDecimal;
+use serde::{Deserialize, Serialize};
+
+use crate::db::enums::{AccessKeyAction, AccessKeyPermission, ExecutionStatus};
+use crate::db::AccessKey;
+
+/// Format of the file with dumped access keys, one access key per line
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum DumpFormat {
+    Jsonl,
+    Csv,
+}
+
+impl std::str::FromStr for DumpFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jsonl" => Ok(Self::Jsonl),
+            "csv" => Ok(Self::Csv),
+            _ => Err(format!("Unknown dump format {}, use jsonl or csv", s)),
+        }
+    }
+}
+
+/// CSV has no lists, so `method_names` are stored as JSON array
+#[derive(Serialize, Deserialize)]
+struct CsvAccessKey {
+    public_key: String,
+    account_id: String,
+    action: AccessKeyAction,
+    status: ExecutionStatus,
+    receipt_hash: String,
+    block_height: BigDecimal,
+    permission: AccessKeyPermission,
+    allowance: Option<BigDecimal>,
+    receiver_id: Option<String>,
+    method_names: Option<String>,
+    nonce: Option<BigDecimal>,
+    block_hash: Option<String>,
+    block_timestamp: Option<BigDecimal>,
+    is_final: bool,
+    #[serde(default)]
+    action_index: i32,
+}
+
+impl From<&AccessKey> for CsvAccessKey {
+    fn from(access_key: &AccessKey) -> Self {
+        Self {
+            public_key: access_key.public_key.clone(),
+            account_id: access_key.account_id.clone(),
+            action: access_key.action.clone(),
+            status: access_key.status,
+            receipt_hash: access_key.receipt_hash.clone(),
+            block_height: access_key.block_height.clone(),
+            permission: access_key.permission.clone(),
+            allowance: access_key.allowance.clone(),
+            receiver_id: access_key.receiver_id.clone(),
+            method_names: access_key.method_names.as_ref().map(|method_names| {
+                serde_json::to_string(method_names).expect("List of strings is always valid JSON")
+            }),
+            nonce: access_key.nonce.clone(),
+            block_hash: access_key.block_hash.clone(),
+            block_timestamp: access_key.block_timestamp.clone(),
+            is_final: access_key.is_final,
+            action_index: access_key.action_index,
+        }
+    }
+}
+
+impl std::convert::TryFrom<CsvAccessKey> for AccessKey {
+    type Error = serde_json::Error;
+
+    fn try_from(csv_access_key: CsvAccessKey) -> Result<Self, Self::Error> {
+        Ok(Self {
+            public_key: csv_access_key.public_key,
+            account_id: csv_access_key.account_id,
+            action: csv_access_key.action,
+            status: csv_access_key.status,
+            receipt_hash: csv_access_key.receipt_hash,
+            block_height: csv_access_key.block_height,
+            permission: csv_access_key.permission,
+            allowance: csv_access_key.allowance,
+            receiver_id: csv_access_key.receiver_id,
+            method_names: csv_access_key
+                .method_names
+                .map(|method_names| serde_json::from_str(&method_names))
+                .transpose()?,
+            nonce: csv_access_key.nonce,
+            block_hash: csv_access_key.block_hash,
+            block_timestamp: csv_access_key.block_timestamp,
+            is_final: csv_access_key.is_final,
+            action_index: csv_access_key.action_index,
+        })
+    }
+}
+
+pub(crate) enum DumpFileWriter {
+    Jsonl(std::io::BufWriter<std::fs::File>),
+    Csv(csv::Writer<std::fs::File>),
+}
+
+impl DumpFileWriter {
+    pub fn create(path: &std::path::Path, format: DumpFormat) -> std::io::Result<Self> {
+        let file = std::fs::File::create(path)?;
+        Ok(match format {
+            DumpFormat::Jsonl => Self::Jsonl(std::io::BufWriter::new(file)),
+            DumpFormat::Csv => Self::Csv(csv::Writer::from_writer(file)),
+        })
+    }
+
+    pub fn write(&mut self, access_key: &AccessKey) -> std::io::Result<()> {
+        match self {
+            Self::Jsonl(writer) => {
+                serde_json::to_writer(&mut *writer, access_key)?;
+                writer.write_all(b"\n")
+            }
+            Self::Csv(writer) => Ok(writer.serialize(CsvAccessKey::from(access_key))?),
+        }
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Jsonl(writer) => writer.flush(),
+            Self::Csv(writer) => writer.flush(),
+        }
+    }
+}
+
+/// Lazily reads the access keys from the dump file
+pub(crate) fn read_dump_file(
+    path: &std::path::Path,
+    format: DumpFormat,
+) -> std::io::Result<Box<dyn Iterator<Item = std::io::Result<AccessKey>>>> {
+    let file = std::fs::File::open(path)?;
+    Ok(match format {
+        DumpFormat::Jsonl => Box::new(
+            std::io::BufReader::new(file)
+                .lines()
+                .filter(|line| !matches!(line, Ok(line) if line.is_empty()))
+                .map(|line| Ok(serde_json::from_str(&line?)?)),
+        ),
+        DumpFormat::Csv => Box::new(
+            csv::Reader::from_reader(file)
+                .into_deserialize::<CsvAccessKey>()
+                .map(|record| {
+                    use std::convert::TryFrom;
+                    Ok(AccessKey::try_from(record?)?)
+                }),
+        ),
+    })
+}
@@ -0,0 +1,845 @@
+use std::collections::{HashMap, HashSet};
+use std::pin::Pin;
+
+use actix_diesel::dsl::AsyncRunQueryDsl;
+use bigdecimal::ToPrimitive;
+use diesel::sql_types::{BigInt, Numeric, Text};
+use diesel::{Connection, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use futures::{Stream, StreamExt};
+use itertools::Itertools;
+use tokio::sync::mpsc;
+use tokio::time;
+use tracing::info;
+
+use near_indexer::near_primitives::state_record::StateRecord;
+
+use crate::configs::{DumpStateArgs, LoadDumpArgs, StateArgs};
+use crate::db::enums::{AccessKeyAction, AccessKeyPermission, ExecutionStatus};
+use crate::db::{
+    establish_connection, AccessKey, Account, CurrentAccessKey, PermissionDetails, StateDump,
+    StateDumpKey,
+};
+use crate::dump_file::{self, DumpFileWriter};
+use crate::{schema, state_viewer, KeyNonces, INDEXER_FOR_WALLET, INTERVAL};
+
+/// Receipt hash of the access keys inserted by the destructive dump
+pub(crate) const GENESIS_RECEIPT_HASH: &str = "genesis";
+/// Amount of access keys read from the state and inserted at once
+const BATCH_SIZE: usize = 5000;
+/// Amount of batches read ahead of the database and inserted concurrently.
+/// Together with `BATCH_SIZE` it bounds the memory used by the dump
+const BATCHES_IN_FLIGHT: usize = 10;
+
+/// Receipt hash of the synthetic actions recorded by the merging dump
+pub(crate) fn dump_state_receipt_hash(
+    height: near_indexer::near_primitives::types::BlockHeight,
+) -> String {
+    format!("dump-state-{}", height)
+}
+
+/// Access keys and accounts read from the state at once
+pub(crate) struct StateBatch {
+    pub access_keys: Vec<AccessKey>,
+    pub accounts: Vec<Account>,
+}
+
+impl From<Vec<AccessKey>> for StateBatch {
+    fn from(access_keys: Vec<AccessKey>) -> Self {
+        Self {
+            access_keys,
+            accounts: vec![],
+        }
+    }
+}
+
+/// Grabs the access keys from the current state to the database. If `replace` is set,
+/// the whole history is replaced with the dumped keys, otherwise only the discrepancies
+/// between the state and `current_access_keys` are recorded as synthetic actions.
+/// If `output` is set, the dumped keys are written to the file instead of the database.
+/// The state is streamed, so the memory usage doesn't depend on the state size.
+/// Returns an error if the requested block can't be loaded
+pub(crate) async fn dump_existing_access_keys(
+    home_dir: std::path::PathBuf,
+    near_config: near_indexer::NearConfig,
+    args: DumpStateArgs,
+) -> Result<(), String> {
+    let replace = args.replace;
+    let (runtime, state_roots, block_header) = load_trie(&home_dir, &near_config, &args.state)?;
+    let latest_block_height = block_header.height();
+    let dumped_block = StateDump::from(&block_header);
+
+    let receipt_hash = if replace {
+        GENESIS_RECEIPT_HASH.to_string()
+    } else {
+        dump_state_receipt_hash(latest_block_height)
+    };
+    // The accounts are stored only in the database
+    let with_accounts = args.output.is_none();
+    let (state_reader, state_batches) = stream_state(
+        runtime,
+        state_roots,
+        dumped_block.clone(),
+        receipt_hash,
+        &args.state,
+        with_accounts,
+    );
+
+    if let Some(output) = args.output {
+        write_access_keys_to_file(
+            state_batches.map(|state_batch| state_batch.map(|state_batch| state_batch.access_keys)),
+            &output,
+            args.format,
+        )
+        .await?;
+        state_reader
+            .join()
+            .expect("Failed to read access keys from the state");
+        info!(
+            target: INDEXER_FOR_WALLET,
+            "The state at block #{} ({}) is dumped to {}. Run `load-dump` to import it to database.",
+            latest_block_height,
+            block_header.hash(),
+            output.display()
+        );
+        return Ok(());
+    }
+
+    let pool = establish_connection();
+    store_dumped_state(state_batches, &dumped_block, replace, true, &pool).await?;
+    state_reader
+        .join()
+        .expect("Failed to read access keys from the state");
+    record_state_dump(dumped_block, &pool).await;
+    info!(
+        target: INDEXER_FOR_WALLET,
+        "The state is dumped at block #{} ({}), it doesn't include the changes of this block. \
+        Run the indexer with `--sync-from-block {}` to continue from the dumped state.",
+        latest_block_height,
+        block_header.hash(),
+        latest_block_height
+    );
+    Ok(())
+}
+
+/// Returns an error if the state can't be read, the file is incomplete then
+async fn write_access_keys_to_file(
+    mut access_key_batches: impl Stream<Item = Result<Vec<AccessKey>, String>> + Unpin,
+    output: &std::path::Path,
+    format: dump_file::DumpFormat,
+) -> Result<(), String> {
+    let mut writer = DumpFileWriter::create(output, format).expect("Failed to create dump file");
+    let mut total_amount = 0;
+    while let Some(access_keys) = access_key_batches.next().await {
+        let access_keys = access_keys
+            .map_err(|err| format!("{}. The dump file {} is incomplete", err, output.display()))?;
+        for access_key in &access_keys {
+            writer
+                .write(access_key)
+                .expect("Failed to write access key to dump file");
+        }
+        total_amount += access_keys.len();
+        info!(
+            target: INDEXER_FOR_WALLET,
+            "Dump state public access keys written: {}", total_amount
+        );
+    }
+    writer.flush().expect("Failed to write access key to dump file");
+    Ok(())
+}
+
+/// Writes the dumped state to the database. If `replace` is set, all the access keys (and the
+/// accounts if `with_accounts` is set) are replaced with the dumped ones, otherwise the dumped
+/// keys are merged with the known ones.
+/// Returns an error if the dumped state can't be read. The keys absent in the state are deleted
+/// only once the whole state is read, so the merge never deletes the keys which weren't read
+async fn store_dumped_state(
+    state_batches: impl Stream<Item = Result<StateBatch, String>>,
+    dumped_block: &StateDump,
+    replace: bool,
+    with_accounts: bool,
+    pool: &actix_diesel::Database<PgConnection>,
+) -> Result<(), String> {
+    if replace {
+        diesel::delete(schema::access_keys::table)
+            .execute_async(pool)
+            .await
+            .unwrap();
+        diesel::delete(schema::current_access_keys::table)
+            .execute_async(pool)
+            .await
+            .unwrap();
+        if with_accounts {
+            diesel::delete(schema::accounts::table)
+                .execute_async(pool)
+                .await
+                .unwrap();
+        }
+        insert_dumped_state(state_batches, None, pool)
+            .await
+            .map_err(|err| {
+                format!(
+                    "{}. The access keys are replaced only partially, run the dump again",
+                    err
+                )
+            })?;
+        info!(
+            target: INDEXER_FOR_WALLET,
+            "Dumped state public access keys in database successfully replaced."
+        );
+    } else {
+        let merger = StateMerger::new(
+            dumped_block.clone(),
+            dump_state_receipt_hash(height_of(dumped_block)),
+            pool,
+        )
+        .await;
+        // Only the accounts which are missing in the database are inserted
+        let mut stats = insert_dumped_state(state_batches, Some(&merger), pool)
+            .await
+            .map_err(|err| {
+                format!(
+                    "{}. No access keys are deleted, run the dump again to finish the merge",
+                    err
+                )
+            })?;
+        stats.deleted = merger.delete_remaining_keys(pool).await;
+        info!(
+            target: INDEXER_FOR_WALLET,
+            "Dumped state differs from current access keys: {} missing, {} with different permission, {} deleted, {} with advanced nonce",
+            stats.added,
+            stats.updated,
+            stats.deleted,
+            stats.advanced_nonces
+        );
+        info!(
+            target: INDEXER_FOR_WALLET,
+            "Dumped state public access keys in database successfully merged."
+        );
+    }
+    Ok(())
+}
+
+async fn record_state_dump(dumped_block: StateDump, pool: &actix_diesel::Database<PgConnection>) {
+    diesel::insert_into(schema::state_dumps::table)
+        .values(dumped_block)
+        .on_conflict_do_nothing()
+        .execute_async(pool)
+        .await
+        .unwrap();
+}
+
+fn height_of(dumped_block: &StateDump) -> near_indexer::near_primitives::types::BlockHeight {
+    dumped_block
+        .block_height
+        .to_u64()
+        .expect("Block height fits into u64")
+}
+
+/// The dumped block is stored in every row of the dump file
+fn dumped_block_of(access_key: &AccessKey) -> Result<StateDump, String> {
+    match (&access_key.block_hash, &access_key.block_timestamp) {
+        (Some(block_hash), Some(block_timestamp)) => Ok(StateDump {
+            block_height: access_key.block_height.clone(),
+            block_hash: block_hash.clone(),
+            block_timestamp: block_timestamp.clone(),
+        }),
+        _ => Err(format!(
+            "The dumped block is unknown: access key {} of {} has no block hash or timestamp",
+            access_key.public_key, access_key.account_id
+        )),
+    }
+}
+
+/// Imports the file written by `dump-state --output` with the same batched insert
+/// as the dump into database, either merging it with the known keys or replacing them.
+/// The file is read lazily batch by batch. The dumped block is taken from the rows of the file
+/// and recorded in `state_dumps`. The file has no accounts, so the accounts are kept as they are
+pub(crate) async fn load_dump(args: LoadDumpArgs) -> Result<(), String> {
+    let mut access_keys = dump_file::read_dump_file(&args.path, args.format)
+        .map_err(|err| format!("Failed to open dump file: {}", err))?
+        .peekable();
+    let dumped_block = match access_keys.peek() {
+        Some(Ok(access_key)) => dumped_block_of(access_key)?,
+        Some(Err(err)) => return Err(format!("Failed to read dump file: {}", err)),
+        None => return Err("Dump file is empty".to_string()),
+    };
+    let receipt_hash = if args.replace {
+        GENESIS_RECEIPT_HASH.to_string()
+    } else {
+        dump_state_receipt_hash(height_of(&dumped_block))
+    };
+
+    let batches_dumped_block = dumped_block.clone();
+    let access_key_batches = futures::stream::iter(std::iter::from_fn(move || {
+        let access_keys: Result<Vec<AccessKey>, String> = access_keys
+            .by_ref()
+            .take(BATCH_SIZE)
+            .map(|access_key| {
+                let mut access_key = access_key
+                    .map_err(|err| format!("Failed to read access key from dump file: {}", err))?;
+                if access_key.block_height != batches_dumped_block.block_height {
+                    return Err(format!(
+                        "Dump file has access keys of different blocks: #{} and #{}",
+                        batches_dumped_block.block_height, access_key.block_height
+                    ));
+                }
+                access_key.receipt_hash = receipt_hash.clone();
+                Ok(access_key)
+            })
+            .collect();
+        match access_keys {
+            Ok(access_keys) if access_keys.is_empty() => None,
+            Ok(access_keys) => Some(Ok(StateBatch::from(access_keys))),
+            Err(err) => Some(Err(err)),
+        }
+    }));
+    let pool = establish_connection();
+    store_dumped_state(
+        access_key_batches,
+        &dumped_block,
+        args.replace,
+        false,
+        &pool,
+    )
+    .await?;
+    record_state_dump(dumped_block.clone(), &pool).await;
+    info!(
+        target: INDEXER_FOR_WALLET,
+        "Dumped access keys from {} at block #{} ({}) are loaded to database. \
+        Run the indexer with `--sync-from-block {}` to continue from the dumped state.",
+        args.path.display(),
+        dumped_block.block_height,
+        dumped_block.block_hash,
+        dumped_block.block_height
+    );
+    Ok(())
+}
+
+pub(crate) fn load_trie(
+    home_dir: &std::path::Path,
+    near_config: &near_indexer::NearConfig,
+    args: &StateArgs,
+) -> Result<
+    (
+        neard::NightshadeRuntime,
+        Vec<near_indexer::near_primitives::types::StateRoot>,
+        near_indexer::near_primitives::block_header::BlockHeader,
+    ),
+    String,
+> {
+    let store = near_store::create_store(&neard::get_store_path(home_dir));
+    state_viewer::load_trie_stop_at_height(store, home_dir, near_config, args.load_trie_mode())
+}
+
+/// Reads the access keys (and the accounts if requested) from the state on a separate thread
+/// and streams them in batches. The dumped keys get the specified receipt hash and block.
+/// If the state can't be read, the error is streamed instead of the rest of the batches
+pub(crate) fn stream_state(
+    runtime: neard::NightshadeRuntime,
+    state_roots: Vec<near_indexer::near_primitives::types::StateRoot>,
+    dumped_block: StateDump,
+    receipt_hash: String,
+    args: &StateArgs,
+    with_accounts: bool,
+) -> (
+    std::thread::JoinHandle<()>,
+    impl Stream<Item = Result<StateBatch, String>> + Unpin,
+) {
+    let full_trie_scan = args.full_trie_scan;
+    let jobs = args.jobs;
+    let columns = if with_accounts {
+        vec![state_viewer::col::ACCOUNT, state_viewer::col::ACCESS_KEY]
+    } else {
+        vec![state_viewer::col::ACCESS_KEY]
+    };
+    let (sender, receiver) = mpsc::channel(BATCHES_IN_FLIGHT);
+    let state_reader = std::thread::spawn(move || {
+        state_viewer::stream_state_records(
+            runtime,
+            state_roots,
+            &columns,
+            full_trie_scan,
+            jobs,
+            BATCH_SIZE,
+            sender,
+        )
+    });
+    let state_batches = tokio_stream::wrappers::ReceiverStream::new(receiver).map(
+        move |records: state_viewer::StateRecordsBatch| {
+            records.map(|records| StateBatch {
+                accounts: accounts_from_state_records(&records),
+                access_keys: access_keys_from_state_records(records, &dumped_block, &receipt_hash),
+            })
+        },
+    );
+    (state_reader, state_batches)
+}
+
+/// Reads the access keys from the state and streams them ordered by `account_id` (byte order),
+/// so they can be merged with another sorted stream. Every shard is read on its own thread in
+/// the trie order, which is the order of the accounts, and the shards are merged, so only
+/// the current batches of the shards are kept in memory. `--jobs` is not used, all the shards
+/// are read at once. If the state can't be read, the error is streamed instead of the rest
+pub(crate) fn stream_state_access_keys_by_account(
+    runtime: neard::NightshadeRuntime,
+    state_roots: Vec<near_indexer::near_primitives::types::StateRoot>,
+    dumped_block: StateDump,
+    receipt_hash: String,
+    args: &StateArgs,
+) -> (
+    std::thread::JoinHandle<()>,
+    impl Stream<Item = Result<AccessKey, String>> + Unpin,
+) {
+    let full_trie_scan = args.full_trie_scan;
+    let (senders, receivers): (Vec<_>, Vec<_>) = state_roots
+        .iter()
+        .map(|_| mpsc::channel(BATCHES_IN_FLIGHT))
+        .unzip();
+    let state_reader = std::thread::spawn(move || {
+        state_viewer::stream_state_records_by_shard(
+            runtime,
+            state_roots,
+            &[state_viewer::col::ACCESS_KEY],
+            full_trie_scan,
+            BATCH_SIZE,
+            senders,
+        )
+    });
+    let shards: Vec<_> = receivers
+        .into_iter()
+        .map(|receiver| {
+            let dumped_block = dumped_block.clone();
+            let receipt_hash = receipt_hash.clone();
+            tokio_stream::wrappers::ReceiverStream::new(receiver)
+                .flat_map(move |records: state_viewer::StateRecordsBatch| {
+                    futures::stream::iter(match records {
+                        Ok(records) => {
+                            access_keys_from_state_records(records, &dumped_block, &receipt_hash)
+                                .into_iter()
+                                .map(Ok)
+                                .collect::<Vec<_>>()
+                        }
+                        Err(err) => vec![Err(err)],
+                    })
+                })
+                .peekable()
+        })
+        .collect();
+    let access_keys = futures::stream::unfold(shards, |mut shards| async move {
+        // The shard with the lowest account goes next, an error goes right away
+        let mut next_shard: Option<(usize, String)> = None;
+        for (index, shard) in shards.iter_mut().enumerate() {
+            match Pin::new(shard).peek().await {
+                Some(Ok(access_key)) => {
+                    if next_shard
+                        .as_ref()
+                        .map_or(true, |(_, account_id)| &access_key.account_id < account_id)
+                    {
+                        next_shard = Some((index, access_key.account_id.clone()));
+                    }
+                }
+                Some(Err(_)) => {
+                    next_shard = Some((index, String::new()));
+                    break;
+                }
+                None => {}
+            }
+        }
+        let (index, _) = next_shard?;
+        let access_key = shards[index].next().await?;
+        Some((access_key, shards))
+    });
+    (state_reader, Box::pin(access_keys))
+}
+
+fn accounts_from_state_records(records: &[StateRecord]) -> Vec<Account> {
+    records
+        .iter()
+        .filter_map(|record| {
+            if let StateRecord::Account { account_id, .. } = record {
+                Some(Account::from_state(account_id))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+fn access_keys_from_state_records(
+    records: Vec<StateRecord>,
+    dumped_block: &StateDump,
+    receipt_hash: &str,
+) -> Vec<AccessKey> {
+    records
+        .into_iter()
+        .filter_map(|record| {
+            if let StateRecord::AccessKey {
+                account_id,
+                public_key,
+                access_key,
+            } = record
+            {
+                let details = PermissionDetails::from(&access_key.permission);
+                Some(AccessKey {
+                    public_key: public_key.to_string(),
+                    account_id: account_id.to_string(),
+                    action: AccessKeyAction::Add,
+                    status: ExecutionStatus::Success,
+                    receipt_hash: receipt_hash.to_string(),
+                    block_height: dumped_block.block_height.clone(),
+                    permission: (&access_key.permission).into(),
+                    allowance: details.allowance,
+                    receiver_id: details.receiver_id,
+                    method_names: details.method_names,
+                    nonce: Some(access_key.nonce.into()),
+                    block_hash: Some(dumped_block.block_hash.clone()),
+                    block_timestamp: Some(dumped_block.block_timestamp.clone()),
+                    // The dumped state is not rolled back
+                    is_final: true,
+                    action_index: 0,
+                })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Allowance is not compared since it decreases every time the key is used
+pub(crate) fn is_same_permission(
+    current_access_key: &CurrentAccessKey,
+    access_key: &AccessKey,
+) -> bool {
+    current_access_key.permission == access_key.permission
+        && current_access_key.receiver_id == access_key.receiver_id
+        && current_access_key.method_names == access_key.method_names
+}
+
+/// Amount of the known keys read at once while looking for the keys absent in the state
+const REMAINING_KEYS_PAGE_SIZE: i64 = 5000;
+
+/// Computes synthetic actions bringing `current_access_keys` in line with the dumped state:
+/// `ADD` actions for the keys which are missing (or have different permission) and `DELETE`
+/// actions for the keys which are absent in the state. The keys which have successful actions
+/// after the dumped height are left as they are.
+/// Nothing is kept in memory: every batch of the dumped keys is compared with the known keys of
+/// the same accounts and recorded in `state_dump_keys`, the known keys missing there are read
+/// page by page once the whole state is merged. Only one merging dump may run at a time
+#[derive(Clone)]
+struct StateMerger {
+    dumped_block: StateDump,
+    receipt_hash: String,
+}
+
+/// Amounts of the differences between the dumped state and `current_access_keys`
+#[derive(Default)]
+struct MergeStats {
+    added: usize,
+    updated: usize,
+    deleted: usize,
+    advanced_nonces: usize,
+}
+
+impl std::ops::AddAssign for MergeStats {
+    fn add_assign(&mut self, other: Self) {
+        self.added += other.added;
+        self.updated += other.updated;
+        self.deleted += other.deleted;
+        self.advanced_nonces += other.advanced_nonces;
+    }
+}
+
+impl StateMerger {
+    /// Forgets the keys recorded by the previous merging dump
+    async fn new(
+        dumped_block: StateDump,
+        receipt_hash: String,
+        pool: &actix_diesel::Database<PgConnection>,
+    ) -> Self {
+        diesel::delete(schema::state_dump_keys::table)
+            .execute_async(pool)
+            .await
+            .expect("Failed to clean up state dump keys");
+        Self {
+            dumped_block,
+            receipt_hash,
+        }
+    }
+
+    /// Successful actions of the given accounts after the dumped height
+    fn changed_after_dump(
+        &self,
+        conn: &PgConnection,
+        account_ids: &[String],
+    ) -> diesel::QueryResult<HashSet<(String, String)>> {
+        Ok(schema::access_keys::table
+            .filter(schema::access_keys::dsl::account_id.eq_any(account_ids))
+            .filter(schema::access_keys::dsl::status.eq(ExecutionStatus::Success))
+            .filter(schema::access_keys::dsl::block_height.gt(&self.dumped_block.block_height))
+            .select((
+                schema::access_keys::dsl::account_id,
+                schema::access_keys::dsl::public_key,
+            ))
+            .load::<(String, String)>(conn)?
+            .into_iter()
+            .collect())
+    }
+
+    /// Returns synthetic `ADD` actions for the batch of the dumped keys and advances the nonces
+    /// of the known keys which are behind the state.
+    /// Is expected to be called within the transaction which inserts the batch
+    fn merge(
+        &self,
+        conn: &PgConnection,
+        state_access_keys: Vec<AccessKey>,
+    ) -> diesel::QueryResult<(Vec<AccessKey>, MergeStats)> {
+        let mut stats = MergeStats::default();
+        if state_access_keys.is_empty() {
+            return Ok((vec![], stats));
+        }
+        let dumped_pairs: Vec<StateDumpKey> = state_access_keys
+            .iter()
+            .map(|access_key| StateDumpKey {
+                account_id: access_key.account_id.clone(),
+                public_key: access_key.public_key.clone(),
+            })
+            .collect();
+        diesel::insert_into(schema::state_dump_keys::table)
+            .values(&dumped_pairs)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+
+        let account_ids: Vec<String> = state_access_keys
+            .iter()
+            .map(|access_key| access_key.account_id.clone())
+            .unique()
+            .collect();
+        let changed_after_dump = self.changed_after_dump(conn, &account_ids)?;
+        let mut current_access_keys: HashMap<(String, String), CurrentAccessKey> =
+            schema::current_access_keys::table
+                .filter(schema::current_access_keys::dsl::account_id.eq_any(&account_ids))
+                .filter(schema::current_access_keys::dsl::is_deleted.eq(false))
+                .load::<CurrentAccessKey>(conn)?
+                .into_iter()
+                .map(|current_access_key| {
+                    (
+                        (
+                            current_access_key.account_id.clone(),
+                            current_access_key.public_key.clone(),
+                        ),
+                        current_access_key,
+                    )
+                })
+                .collect();
+
+        let mut synthetic_actions: Vec<AccessKey> = vec![];
+        let mut advanced_nonces = KeyNonces::new();
+        for access_key in state_access_keys {
+            let pair = (access_key.account_id.clone(), access_key.public_key.clone());
+            if changed_after_dump.contains(&pair) {
+                continue;
+            }
+            match current_access_keys.remove(&pair) {
+                None => {
+                    stats.added += 1;
+                    synthetic_actions.push(access_key);
+                }
+                Some(current_access_key)
+                    if !is_same_permission(&current_access_key, &access_key) =>
+                {
+                    stats.updated += 1;
+                    synthetic_actions.push(access_key);
+                }
+                Some(current_access_key) => {
+                    // The indexer may have missed the transactions signed by the key
+                    if access_key.nonce > current_access_key.nonce {
+                        if let Some(nonce) = access_key.nonce {
+                            advanced_nonces.insert(pair, nonce);
+                        }
+                    }
+                }
+            }
+        }
+        stats.advanced_nonces = advanced_nonces.len();
+        crate::update_current_access_key_nonces(conn, &advanced_nonces)?;
+        Ok((synthetic_actions, stats))
+    }
+
+    /// Returns synthetic `DELETE` actions for the next page of the known keys which weren't met
+    /// in the state, starting after the `after` pair. It must be called after all the dumped
+    /// keys are merged
+    fn remaining_keys_page(
+        &self,
+        conn: &PgConnection,
+        after: &(String, String),
+    ) -> diesel::QueryResult<Vec<AccessKey>> {
+        let remaining_keys: Vec<CurrentAccessKey> = diesel::sql_query(
+            r#"
+            SELECT current_access_keys.*
+            FROM current_access_keys
+            WHERE NOT current_access_keys.is_deleted
+                AND (current_access_keys.account_id, current_access_keys.public_key) > ($1, $2)
+                AND NOT EXISTS (
+                    SELECT 1
+                    FROM state_dump_keys
+                    WHERE state_dump_keys.account_id = current_access_keys.account_id
+                        AND state_dump_keys.public_key = current_access_keys.public_key
+                )
+                AND NOT EXISTS (
+                    SELECT 1
+                    FROM access_keys
+                    WHERE access_keys.account_id = current_access_keys.account_id
+                        AND access_keys.public_key = current_access_keys.public_key
+                        AND access_keys.status = 'SUCCESS'
+                        AND access_keys.block_height > $3
+                )
+            ORDER BY current_access_keys.account_id, current_access_keys.public_key
+            LIMIT $4
+            "#,
+        )
+        .bind::<Text, _>(&after.0)
+        .bind::<Text, _>(&after.1)
+        .bind::<Numeric, _>(&self.dumped_block.block_height)
+        .bind::<BigInt, _>(REMAINING_KEYS_PAGE_SIZE)
+        .load(conn)?;
+
+        Ok(remaining_keys
+            .into_iter()
+            .map(|current_access_key| AccessKey {
+                public_key: current_access_key.public_key,
+                account_id: current_access_key.account_id,
+                action: AccessKeyAction::Delete,
+                status: ExecutionStatus::Success,
+                receipt_hash: self.receipt_hash.clone(),
+                block_height: self.dumped_block.block_height.clone(),
+                permission: AccessKeyPermission::NotApplicable,
+                allowance: None,
+                receiver_id: None,
+                method_names: None,
+                nonce: None,
+                block_hash: Some(self.dumped_block.block_hash.clone()),
+                block_timestamp: Some(self.dumped_block.block_timestamp.clone()),
+                is_final: true,
+                action_index: 0,
+            })
+            .collect())
+    }
+
+    /// Records synthetic `DELETE` actions for all the known keys which weren't met in the state
+    /// page by page and forgets the dumped keys
+    async fn delete_remaining_keys(&self, pool: &actix_diesel::Database<PgConnection>) -> usize {
+        let mut after = (String::new(), String::new());
+        let mut deleted_amount = 0;
+        loop {
+            let page = loop {
+                let merger = self.clone();
+                let page_after = after.clone();
+                match pool
+                    .get(move |conn| {
+                        conn.transaction(|| {
+                            let deleted_keys = merger.remaining_keys_page(conn, &page_after)?;
+                            diesel::insert_into(schema::access_keys::table)
+                                .values(&deleted_keys)
+                                .on_conflict_do_nothing()
+                                .execute(conn)?;
+                            crate::update_current_access_keys(conn, &deleted_keys)?;
+                            Ok(deleted_keys)
+                        })
+                    })
+                    .await
+                {
+                    Ok(page) => break page,
+                    Err(err) => {
+                        info!(
+                            target: INDEXER_FOR_WALLET,
+                            "Trying to delete access keys absent in dumped state failed with: {:?}. Retrying in {} seconds...",
+                            err,
+                            INTERVAL.as_secs_f32()
+                        );
+                        time::sleep(INTERVAL).await;
+                    }
+                }
+            };
+            let last_key = match page.last() {
+                Some(last_key) => last_key,
+                None => break,
+            };
+            after = (last_key.account_id.clone(), last_key.public_key.clone());
+            deleted_amount += page.len();
+        }
+        diesel::delete(schema::state_dump_keys::table)
+            .execute_async(pool)
+            .await
+            .expect("Failed to clean up state dump keys");
+        deleted_amount
+    }
+}
+
+/// Inserts the batches of the dumped state. If `merger` is set, only the differences with
+/// `current_access_keys` are recorded. Returns the amounts of the differences or the error
+/// of the first batch which couldn't be read, the rest of the batches are not inserted then
+async fn insert_dumped_state(
+    state_batches: impl Stream<Item = Result<StateBatch, String>>,
+    merger: Option<&StateMerger>,
+    pool: &actix_diesel::Database<PgConnection>,
+) -> Result<MergeStats, String> {
+    let mut insert_genesis_keys = state_batches
+        .map(|state_batch| async move {
+            let state_batch = state_batch?;
+            let amount = state_batch.access_keys.len();
+            if amount == 0 && state_batch.accounts.is_empty() {
+                return Ok((amount, MergeStats::default()));
+            }
+            loop {
+                let access_keys = state_batch.access_keys.clone();
+                let accounts = state_batch.accounts.clone();
+                let merger = merger.cloned();
+                match pool
+                    .get(move |conn| {
+                        conn.transaction(|| {
+                            diesel::insert_into(schema::accounts::table)
+                                .values(&accounts)
+                                .on_conflict_do_nothing()
+                                .execute(conn)?;
+                            let (access_keys, stats) = match merger {
+                                Some(merger) => merger.merge(conn, access_keys)?,
+                                None => (access_keys, MergeStats::default()),
+                            };
+                            diesel::insert_into(schema::access_keys::table)
+                                .values(&access_keys)
+                                .on_conflict_do_nothing()
+                                .execute(conn)?;
+                            crate::update_current_access_keys(conn, &access_keys)?;
+                            Ok(stats)
+                        })
+                    })
+                    .await
+                {
+                    Ok(stats) => break Ok((amount, stats)),
+                    Err(err) => {
+                        info!(
+                            target: INDEXER_FOR_WALLET,
+                            "Trying to push dumped state access keys failed with: {:?}. Retrying in {} seconds...",
+                            err,
+                            INTERVAL.as_secs_f32()
+                        );
+                        time::sleep(INTERVAL).await;
+                    }
+                }
+            }
+        })
+        .buffer_unordered(BATCHES_IN_FLIGHT);
+
+    let mut total_amount = 0;
+    let mut total_stats = MergeStats::default();
+    while let Some(inserted) = insert_genesis_keys.next().await {
+        let (amount, stats) = inserted?;
+        total_amount += amount;
+        total_stats += stats;
+        info!(
+            target: INDEXER_FOR_WALLET,
+            "Dump state public access keys added: {}", total_amount
+        );
+    }
+    Ok(total_stats)
+}
@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+
+use diesel::sql_types::BigInt;
+use diesel::{ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use tracing::{info, warn};
+
+use crate::db::IndexerCheckpoint;
+use crate::{schema, INDEXER_FOR_WALLET};
+
+/// Key of the transaction level advisory lock taken while the finality is applied, so
+/// the blocks stored concurrently never apply the changes of the same final block twice
+const FINALITY_LOCK_KEY: i64 = 0x6669_6e61_6c69_7479;
+
+/// Marks the blocks which became final along with their rows and rolls back the rows of
+/// the blocks which can never become final (forks). Returns the newly final blocks ordered
+/// by height, their staged changes are expected to be applied by the caller.
+///
+/// A block is known to be final if any stored block names it as `last_final_block` or if it is
+/// the parent of a final block. The final chain is restored by walking `prev_block_hash` from
+/// the highest known final block down to the previously finalized part of the chain. Blocks are
+/// stored concurrently, so if the walk meets a gap, the lower known final blocks are tried and
+/// the rest waits until the gap is filled. The only gap which can't be filled is below the first
+/// stored block, so the chain is complete as well if nothing is stored below it.
+/// Is expected to be called within the transaction which stores the block
+pub(crate) fn apply_finality(conn: &PgConnection) -> diesel::QueryResult<Vec<IndexerCheckpoint>> {
+    diesel::sql_query("SELECT pg_advisory_xact_lock($1)")
+        .bind::<BigInt, _>(FINALITY_LOCK_KEY)
+        .execute(conn)?;
+
+    let final_candidates: Vec<IndexerCheckpoint> = diesel::sql_query(
+        r#"
+        SELECT checkpoint.*
+        FROM indexer_checkpoints AS checkpoint
+        WHERE NOT checkpoint.is_final
+            AND EXISTS (
+                SELECT 1
+                FROM indexer_checkpoints AS descendant
+                WHERE descendant.last_final_block_hash = checkpoint.block_hash
+                    OR (descendant.is_final AND descendant.prev_block_hash = checkpoint.block_hash)
+            )
+        ORDER BY checkpoint.block_height DESC
+        "#,
+    )
+    .load(conn)?;
+
+    let mut newly_final_checkpoints: Vec<IndexerCheckpoint> = vec![];
+    if !final_candidates.is_empty() {
+        let not_final_checkpoints: HashMap<String, IndexerCheckpoint> =
+            schema::indexer_checkpoints::table
+                .filter(schema::indexer_checkpoints::dsl::is_final.eq(false))
+                .load::<IndexerCheckpoint>(conn)?
+                .into_iter()
+                .map(|checkpoint| (checkpoint.block_hash.clone(), checkpoint))
+                .collect();
+        for candidate in &final_candidates {
+            if let Some(final_hashes) = final_chain(conn, &not_final_checkpoints, candidate)? {
+                newly_final_checkpoints = mark_final(conn, final_hashes)?;
+                break;
+            }
+        }
+        if newly_final_checkpoints.is_empty() {
+            warn!(
+                target: INDEXER_FOR_WALLET,
+                "Block {} is final, but some of its ancestors are not stored yet, the finality is postponed",
+                final_candidates[0].block_hash
+            );
+        }
+    }
+
+    roll_back_orphaned_blocks(conn)?;
+    Ok(newly_final_checkpoints)
+}
+
+/// Hashes of the not yet final blocks from `last_final_checkpoint` down to the previously
+/// finalized part of the chain, `None` if there is a gap in the stored blocks
+fn final_chain(
+    conn: &PgConnection,
+    not_final_checkpoints: &HashMap<String, IndexerCheckpoint>,
+    last_final_checkpoint: &IndexerCheckpoint,
+) -> diesel::QueryResult<Option<Vec<String>>> {
+    let mut final_hashes: Vec<String> = vec![];
+    let mut lowest_final_height = &last_final_checkpoint.block_height;
+    let mut block_hash = last_final_checkpoint.block_hash.as_str();
+    while let Some(checkpoint) = not_final_checkpoints.get(block_hash) {
+        final_hashes.push(checkpoint.block_hash.clone());
+        lowest_final_height = &checkpoint.block_height;
+        block_hash = checkpoint.prev_block_hash.as_str();
+    }
+
+    let reached_final_block = schema::indexer_checkpoints::table
+        .filter(schema::indexer_checkpoints::dsl::block_hash.eq(block_hash))
+        .filter(schema::indexer_checkpoints::dsl::is_final.eq(true))
+        .count()
+        .get_result::<i64>(conn)?
+        > 0;
+    let is_chain_complete = reached_final_block
+        || schema::indexer_checkpoints::table
+            .filter(schema::indexer_checkpoints::dsl::block_height.lt(lowest_final_height))
+            .count()
+            .get_result::<i64>(conn)?
+            == 0;
+    Ok(if is_chain_complete {
+        Some(final_hashes)
+    } else {
+        None
+    })
+}
+
+fn mark_final(
+    conn: &PgConnection,
+    final_hashes: Vec<String>,
+) -> diesel::QueryResult<Vec<IndexerCheckpoint>> {
+    diesel::update(
+        schema::access_keys::table
+            .filter(schema::access_keys::dsl::block_hash.eq_any(&final_hashes)),
+    )
+    .set(schema::access_keys::dsl::is_final.eq(true))
+    .execute(conn)?;
+    let mut newly_final_checkpoints: Vec<IndexerCheckpoint> = diesel::update(
+        schema::indexer_checkpoints::table
+            .filter(schema::indexer_checkpoints::dsl::block_hash.eq_any(&final_hashes))
+            .filter(schema::indexer_checkpoints::dsl::is_final.eq(false)),
+    )
+    .set(schema::indexer_checkpoints::dsl::is_final.eq(true))
+    .get_results(conn)?;
+    newly_final_checkpoints.sort_by(|a, b| a.block_height.cmp(&b.block_height));
+    Ok(newly_final_checkpoints)
+}
+
+/// Removes the blocks which are proven to be off the final chain: there is a final block at
+/// the same height or the final chain skips the height (the lowest final block above it has
+/// the parent below it). The blocks stored below the known part of the final chain are kept,
+/// they may be its ancestors stored out of order. Only the rows of the block itself and its
+/// staged changes are removed, nothing else is changed before the block is final
+fn roll_back_orphaned_blocks(conn: &PgConnection) -> diesel::QueryResult<()> {
+    let orphaned_hashes: Vec<String> = diesel::sql_query(
+        r#"
+        SELECT orphan.*
+        FROM indexer_checkpoints AS orphan
+        WHERE NOT orphan.is_final
+            AND (
+                EXISTS (
+                    SELECT 1
+                    FROM indexer_checkpoints AS final_block
+                    WHERE final_block.is_final
+                        AND final_block.block_height = orphan.block_height
+                )
+                OR (
+                    SELECT final_block_parent.block_height
+                    FROM indexer_checkpoints AS final_block
+                    JOIN indexer_checkpoints AS final_block_parent
+                        ON final_block_parent.block_hash = final_block.prev_block_hash
+                    WHERE final_block.is_final
+                        AND final_block.block_height > orphan.block_height
+                    ORDER BY final_block.block_height
+                    LIMIT 1
+                ) < orphan.block_height
+            )
+        "#,
+    )
+    .load::<IndexerCheckpoint>(conn)?
+    .into_iter()
+    .map(|checkpoint| checkpoint.block_hash)
+    .collect();
+    if orphaned_hashes.is_empty() {
+        return Ok(());
+    }
+
+    info!(
+        target: INDEXER_FOR_WALLET,
+        "Rolling back {} blocks which are not on the final chain: {:?}",
+        orphaned_hashes.len(),
+        orphaned_hashes
+    );
+    diesel::delete(
+        schema::access_keys::table
+            .filter(schema::access_keys::dsl::block_hash.eq_any(&orphaned_hashes))
+            .filter(schema::access_keys::dsl::is_final.eq(false)),
+    )
+    .execute(conn)?;
+    diesel::delete(
+        schema::staged_changes::table
+            .filter(schema::staged_changes::dsl::block_hash.eq_any(&orphaned_hashes)),
+    )
+    .execute(conn)?;
+    diesel::delete(
+        schema::indexer_checkpoints::table
+            .filter(schema::indexer_checkpoints::dsl::block_hash.eq_any(&orphaned_hashes)),
+    )
+    .execute(conn)?;
+    Ok(())
+}
@@ -0,0 +1,104 @@
+use std::convert::TryFrom;
+
+/// Rule deriving the access key of the implicit account from its account id.
+/// Implicit account is created by the transfer to its id and gets the only full access key
+pub(crate) trait ImplicitAccountRule: Sync {
+    /// Type of the keys derived by the rule
+    fn key_type(&self) -> near_crypto::KeyType;
+
+    /// Returns the access key of the implicit account or `None` if the account id
+    /// doesn't match the rule
+    fn public_key(&self, account_id: &str) -> Option<near_crypto::PublicKey>;
+}
+
+/// Implicit account id is the lowercase hex of the ED25519 public key
+pub(crate) struct Ed25519ImplicitAccount;
+
+impl ImplicitAccountRule for Ed25519ImplicitAccount {
+    fn key_type(&self) -> near_crypto::KeyType {
+        near_crypto::KeyType::ED25519
+    }
+
+    fn public_key(&self, account_id: &str) -> Option<near_crypto::PublicKey> {
+        if account_id.len() != 64
+            || !account_id
+                .chars()
+                .all(|c| matches!(c, '0'..='9' | 'a'..='f'))
+        {
+            return None;
+        }
+        let public_key_bytes = hex::decode(account_id).ok()?;
+        near_crypto::ED25519PublicKey::try_from(&public_key_bytes[..])
+            .ok()
+            .map(near_crypto::PublicKey::from)
+    }
+}
+
+/// The rules are tried in order, the first matching one wins.
+/// New key types are supported by adding their rules here
+const RULES: &[&dyn ImplicitAccountRule] = &[&Ed25519ImplicitAccount];
+
+/// Access key of the implicit account or `None` if the account is not implicit.
+/// The key is stored as `<key type>:<base58>` like all the other keys
+pub(crate) fn implicit_account_public_key(account_id: &str) -> Option<near_crypto::PublicKey> {
+    RULES.iter().find_map(|rule| {
+        rule.public_key(account_id)
+            .filter(|public_key| public_key.key_type() == rule.key_type())
+    })
+}
+
+pub(crate) fn is_implicit_account_id(account_id: &str) -> bool {
+    implicit_account_public_key(account_id).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+
+    fn ed25519_account_id(key_seed: &str) -> String {
+        match test_utils::public_key(key_seed) {
+            near_crypto::PublicKey::ED25519(public_key) => hex::encode(public_key.0),
+            _ => unreachable!("Test keys are ED25519"),
+        }
+    }
+
+    #[test]
+    fn ed25519_account_gets_its_key() {
+        assert_eq!(
+            implicit_account_public_key(&ed25519_account_id("k1")),
+            Some(test_utils::public_key("k1"))
+        );
+    }
+
+    #[test]
+    fn ed25519_account_does_not_get_other_keys() {
+        assert_ne!(
+            implicit_account_public_key(&ed25519_account_id("k1")),
+            Some(test_utils::public_key("k2"))
+        );
+    }
+
+    #[test]
+    fn wrong_length_account_is_not_implicit() {
+        let account_id = ed25519_account_id("k1");
+        assert!(!is_implicit_account_id(&account_id[..63]));
+        assert!(!is_implicit_account_id(&format!("{}00", account_id)));
+        assert!(!is_implicit_account_id("alice.near"));
+    }
+
+    #[test]
+    fn uppercase_account_is_not_implicit() {
+        assert!(!is_implicit_account_id(
+            &ed25519_account_id("k1").to_uppercase()
+        ));
+    }
+
+    /// SECP256K1 keys are 64 bytes long, there is no rule for them, so they fall through
+    #[test]
+    fn secp256k1_account_is_not_implicit() {
+        let account_id = "0123456789abcdef".repeat(8);
+        assert_eq!(Ed25519ImplicitAccount.public_key(&account_id), None);
+        assert!(!is_implicit_account_id(&account_id));
+    }
+}
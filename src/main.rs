@@ -1,30 +1,54 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::time::Duration;
 
+use bigdecimal::BigDecimal;
 use clap::Clap;
 #[macro_use]
 extern crate diesel;
+#[cfg(test)]
+#[macro_use]
+extern crate diesel_migrations;
 use actix_diesel::dsl::AsyncRunQueryDsl;
-use diesel::{ExpressionMethods, PgConnection, QueryDsl};
-use futures::{join, StreamExt};
+use diesel::pg::upsert::excluded;
+use diesel::sql_types::Text;
+use diesel::{
+    BoolExpressionMethods, Connection, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl,
+};
+use futures::StreamExt;
 use itertools::Itertools;
 use tokio::sync::mpsc;
 use tokio::time;
 use tracing::{debug, error, info, warn};
 use tracing_subscriber::EnvFilter;
 
-use near_chain_configs::GenesisRecords;
-
 use crate::configs::{Opts, SubCommand};
-use crate::db::enums::{AccessKeyAction, ExecutionStatus};
-use crate::db::{establish_connection, AccessKey};
+use crate::db::enums::ExecutionStatus;
+use crate::db::{
+    establish_connection, AccessKey, AccessKeyUsage, Account, AccountChange, Alert, BlockChanges,
+    CurrentAccessKey, IndexerCheckpoint, KeyNonce, PendingOutcome, ReceiptOutcome, StagedChanges,
+};
 
+mod alerts;
+mod api;
 mod configs;
 mod db;
+mod dump_file;
+mod dump_state;
+mod finality;
+mod implicit_accounts;
+mod metrics;
+mod notifications;
 mod schema;
 mod state_viewer;
+#[cfg(test)]
+mod test_utils;
+mod verify;
+mod webhooks;
 
 const INTERVAL: Duration = Duration::from_millis(100);
+const PERIODIC_TASKS_INTERVAL: Duration = Duration::from_secs(60);
+/// How often `blocks_per_second` metric is updated
+const BLOCKS_RATE_INTERVAL: Duration = Duration::from_secs(10);
 const INDEXER_FOR_WALLET: &str = "indexer_for_wallet";
 
 /// Map Receipt ID to Execution Outcome
@@ -33,116 +57,16 @@ pub type ExecutionOutcomesByReceiptId = HashMap<
     near_indexer::near_primitives::views::ExecutionOutcomeWithIdView,
 >;
 
-async fn dump_existing_access_keys(
-    home_dir: std::path::PathBuf,
-    near_config: near_indexer::NearConfig,
-) {
-    let (records, latest_block_height) = extract_state_as_genesis_records(home_dir, near_config);
-    let pool = establish_connection();
-    diesel::delete(schema::access_keys::table)
-        .execute_async(&pool)
-        .await
-        .unwrap();
-    insert_access_keys_from_dumped_state(records, latest_block_height, &pool).await;
-}
-
-fn extract_state_as_genesis_records(
-    home_dir: std::path::PathBuf,
-    near_config: near_indexer::NearConfig,
-) -> (
-    GenesisRecords,
-    near_indexer::near_primitives::types::BlockHeight,
-) {
-    let store = near_store::create_store(&neard::get_store_path(&home_dir));
-
-    let (runtime, state_roots, latest_block_header) = state_viewer::load_trie_stop_at_height(
-        store,
-        &home_dir,
-        &near_config,
-        state_viewer::LoadTrieMode::Latest,
-    );
-
-    let latest_block_height = latest_block_header.height();
-    let dumped_state_genesis = state_viewer::state_dump(
-        runtime,
-        state_roots,
-        latest_block_header,
-        &near_config.genesis.config,
-    );
-
-    (dumped_state_genesis.records, latest_block_height)
-}
-
-async fn insert_access_keys_from_dumped_state(
-    records: GenesisRecords,
-    height: near_indexer::near_primitives::types::BlockHeight,
-    pool: &actix_diesel::Database<PgConnection>,
-) {
-    let access_keys = records.as_ref().iter().filter_map(|record| {
-        if let near_indexer::near_primitives::state_record::StateRecord::AccessKey {
-            account_id,
-            public_key,
-            access_key,
-        } = record
-        {
-            Some(AccessKey {
-                public_key: public_key.to_string(),
-                account_id: account_id.to_string(),
-                action: AccessKeyAction::Add,
-                status: ExecutionStatus::Success,
-                receipt_hash: "genesis".to_string(),
-                block_height: height.into(),
-                permission: (&access_key.permission).into(),
-            })
-        } else {
-            None
-        }
-    });
-
-    let portion_size = 5000;
-    let total_access_key_chunks = access_keys.clone().count() / portion_size + 1;
-    let access_keys_portion = access_keys.chunks(portion_size);
+/// Max nonce for every (`account_id`, `public_key`) pair
+pub(crate) type KeyNonces = HashMap<(String, String), BigDecimal>;
 
-    let insert_genesis_keys: futures::stream::FuturesUnordered<_> = access_keys_portion
-        .into_iter()
-        .map(|keys| async {
-            let collected_keys = keys.collect::<Vec<AccessKey>>();
-            loop {
-                match diesel::insert_into(schema::access_keys::table)
-                    .values(collected_keys.clone())
-                    .on_conflict_do_nothing()
-                    .execute_async(&pool).await {
-                        Ok(result) => break result,
-                        Err(err) => {
-                            info!(target: INDEXER_FOR_WALLET, "Trying to push dumped state access keys failed with: {:?}. Retrying in {} seconds...", err, INTERVAL.as_secs_f32());
-                            time::sleep(INTERVAL).await;
-                        }
-                    }
-                }
-            })
-        .collect();
-
-    let mut insert_genesis_keys = insert_genesis_keys.enumerate();
-
-    while let Some((index, _result)) = insert_genesis_keys.next().await {
-        info!(
-            target: INDEXER_FOR_WALLET,
-            "Dump state public access keys adding {}%",
-            index * 100 / total_access_key_chunks
-        );
-    }
-
-    info!(
-        target: INDEXER_FOR_WALLET,
-        "Dumped state public access keys in database successfully replaced."
-    );
-}
-
-async fn insert_receipts(
-    height: near_indexer::near_primitives::types::BlockHeight,
+/// Returns the inserted access key actions. Their statuses are known if the receipts are
+/// executed in the same block or their final outcomes are kept as pending outcomes
+fn insert_receipts(
+    conn: &PgConnection,
+    block: &near_indexer::near_primitives::views::BlockHeaderView,
     chunks: &[near_indexer::IndexerChunkView],
-    pool: &actix_diesel::Database<PgConnection>,
-) {
+) -> diesel::QueryResult<Vec<AccessKey>> {
     let outcomes = chunks.iter().flat_map(|chunk| {
         chunk.receipt_execution_outcomes.iter().map(|outcome| {
             (
@@ -164,20 +88,19 @@ async fn insert_receipts(
             None
         }
     }
-    let access_keys: Vec<AccessKey> = chunks
-        .iter()
-        .flat_map(|chunk| &chunk.receipts)
-        .flat_map(|receipt| match receipt.receipt {
-            near_indexer::near_primitives::views::ReceiptEnumView::Action { .. } => {
-                AccessKey::from_receipt_view(
-                    receipt,
-                    height,
-                    receipt_status(&outcomes, &receipt.receipt_id),
-                )
-            }
-            _ => vec![],
-        })
-        .collect();
+    let mut access_keys: Vec<AccessKey> = vec![];
+    for receipt in chunks.iter().flat_map(|chunk| &chunk.receipts) {
+        if let near_indexer::near_primitives::views::ReceiptEnumView::Action { .. } =
+            receipt.receipt
+        {
+            access_keys.extend(AccessKey::from_receipt_view(
+                receipt,
+                block,
+                receipt_status(&outcomes, &receipt.receipt_id),
+                &account_keys(conn, receipt)?,
+            ));
+        }
+    }
 
     info!(
         target: INDEXER_FOR_WALLET,
@@ -185,98 +108,359 @@ async fn insert_receipts(
         access_keys.len()
     );
     if !access_keys.is_empty() {
-        loop {
-            match diesel::insert_into(schema::access_keys::table)
-                .values(access_keys.clone())
-                .on_conflict_do_nothing()
-                .execute_async(&pool)
-                .await
-            {
-                Ok(_) => break,
-                Err(async_error) => {
-                    error!(
-                        target: INDEXER_FOR_WALLET,
-                        "Failed to insert access keys, retrying in {} milliseconds... \n {:#?}",
-                        INTERVAL.as_millis(),
-                        async_error
-                    );
-                    time::sleep(INTERVAL).await;
-                }
-            };
+        apply_pending_outcomes(conn, &mut access_keys)?;
+        // The same receipt may be included in the blocks of different forks,
+        // every block has its own rows, so they are rolled back independently
+        diesel::insert_into(schema::access_keys::table)
+            .values(&access_keys)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+    }
+    Ok(access_keys)
+}
+
+#[derive(QueryableByName)]
+struct AccountKey {
+    #[sql_type = "Text"]
+    public_key: String,
+}
+
+/// Live public keys of the account deleted by the receipt. `current_access_keys` follows
+/// the final blocks only, so the keys added by the blocks which are not final yet are included
+/// as well. Other receipts don't need them, so the database is not queried for them
+fn account_keys(
+    conn: &PgConnection,
+    receipt: &near_indexer::near_primitives::views::ReceiptView,
+) -> diesel::QueryResult<Vec<String>> {
+    if !db::access_keys::is_delete_account_receipt(receipt) {
+        return Ok(vec![]);
+    }
+    let account_keys: Vec<AccountKey> = diesel::sql_query(
+        r#"
+        SELECT public_key
+        FROM current_access_keys
+        WHERE account_id = $1 AND NOT is_deleted
+        UNION
+        SELECT latest_action.public_key
+        FROM (
+            SELECT DISTINCT ON (public_key) public_key, "action", status
+            FROM access_keys
+            WHERE account_id = $1 AND NOT is_final
+            ORDER BY public_key, block_height DESC, action_index DESC
+        ) AS latest_action
+        WHERE latest_action."action" = 'ADD' AND latest_action.status <> 'FAILED'
+        "#,
+    )
+    .bind::<Text, _>(receipt.receiver_id.to_string())
+    .load(conn)?;
+    Ok(account_keys
+        .into_iter()
+        .map(|account_key| account_key.public_key)
+        .collect())
+}
+
+/// Sets the statuses of the outcomes which were received before the receipts themselves.
+/// The pending outcomes are kept until the block with the receipts is final
+fn apply_pending_outcomes(
+    conn: &PgConnection,
+    access_keys: &mut [AccessKey],
+) -> diesel::QueryResult<()> {
+    let receipt_ids: Vec<String> = access_keys
+        .iter()
+        .filter(|access_key| matches!(access_key.status, ExecutionStatus::Pending))
+        .map(|access_key| access_key.receipt_hash.clone())
+        .unique()
+        .collect();
+    if receipt_ids.is_empty() {
+        return Ok(());
+    }
+
+    let pending_outcomes: Vec<PendingOutcome> = schema::pending_outcomes::table
+        .filter(schema::pending_outcomes::dsl::receipt_hash.eq_any(receipt_ids))
+        .load(conn)?;
+
+    let statuses: HashMap<String, ExecutionStatus> = pending_outcomes
+        .into_iter()
+        .map(|pending_outcome| (pending_outcome.receipt_hash, pending_outcome.status))
+        .collect();
+    for access_key in access_keys.iter_mut() {
+        if let Some(status) = statuses.get(&access_key.receipt_hash) {
+            access_key.status = *status;
         }
     }
+    if !statuses.is_empty() {
+        info!(
+            target: INDEXER_FOR_WALLET,
+            "Applied {} pending outcomes",
+            statuses.len()
+        );
+    }
+    Ok(())
+}
+
+/// Sets the statuses of the final block rows which are still `PENDING` from the pending outcomes.
+/// Blocks are stored concurrently, so the outcome may be saved as pending after the receipt is
+/// inserted by another block, but before that block is final.
+/// Returns the access key actions with updated status
+fn apply_final_pending_outcomes(
+    conn: &PgConnection,
+    block_hash: &str,
+) -> diesel::QueryResult<Vec<AccessKey>> {
+    let updated_access_keys: Vec<AccessKey> = diesel::sql_query(
+        r#"
+        UPDATE access_keys
+        SET status = pending_outcomes.status
+        FROM pending_outcomes
+        WHERE access_keys.receipt_hash = pending_outcomes.receipt_hash
+            AND access_keys.block_hash = $1
+            AND access_keys.status = 'PENDING'
+        RETURNING access_keys.*
+        "#,
+    )
+    .bind::<Text, _>(block_hash)
+    .load(conn)?;
+    if !updated_access_keys.is_empty() {
+        info!(
+            target: INDEXER_FOR_WALLET,
+            "Applied pending outcomes to {} access key actions of the final block {}",
+            updated_access_keys.len(),
+            block_hash
+        );
+    }
+    Ok(updated_access_keys)
 }
 
-async fn update_receipt_status(
+fn forget_pending_outcomes(
+    conn: &PgConnection,
     receipt_ids: Vec<String>,
-    status: ExecutionStatus,
-    pool: &actix_diesel::Database<PgConnection>,
-) {
-    debug!(target: INDEXER_FOR_WALLET, "update_receipt_status called");
+) -> diesel::QueryResult<()> {
     if receipt_ids.is_empty() {
-        return;
+        return Ok(());
     }
+    diesel::delete(
+        schema::pending_outcomes::table
+            .filter(schema::pending_outcomes::dsl::receipt_hash.eq_any(receipt_ids)),
+    )
+    .execute(conn)?;
+    Ok(())
+}
 
-    let rows_touched = loop {
-        match diesel::update(
-            schema::access_keys::table
-                .filter(schema::access_keys::dsl::receipt_hash.eq_any(receipt_ids.clone())),
-        )
-        .set(schema::access_keys::dsl::status.eq(status))
-        .execute_async(pool)
-        .await
-        {
-            Ok(res) => {
-                break res;
-            }
-            Err(async_error) => {
-                error!(
-                    target: INDEXER_FOR_WALLET,
-                    "Failed to update status, retrying in {} milliseconds... \n {:#?}",
-                    INTERVAL.as_millis(),
-                    async_error
-                );
-                time::sleep(INTERVAL).await
+/// Applies successful `ADD` and `DELETE` actions to the `current_access_keys` table
+fn update_current_access_keys(
+    conn: &PgConnection,
+    access_keys: &[AccessKey],
+) -> diesel::QueryResult<()> {
+    // The latest successful action wins for every (account_id, public_key) pair, the actions
+    // of the same block are ordered by their position in the receipt
+    let mut latest_actions: HashMap<(String, String), &AccessKey> = HashMap::new();
+    for access_key in access_keys
+        .iter()
+        .filter(|access_key| matches!(access_key.status, ExecutionStatus::Success))
+    {
+        let pair = (access_key.account_id.clone(), access_key.public_key.clone());
+        match latest_actions.get(&pair) {
+            Some(latest)
+                if (&latest.block_height, latest.action_index)
+                    > (&access_key.block_height, access_key.action_index) => {}
+            _ => {
+                latest_actions.insert(pair, access_key);
             }
         }
-    };
-    if rows_touched != receipt_ids.len() {
+    }
+
+    // Blocks may be applied out of order, so the action is skipped if a newer one is applied
+    // already. `DELETE` leaves a tombstone for the same reason
+    for access_key in latest_actions.values() {
+        db::current_access_keys::upsert_current_access_key(
+            conn,
+            &CurrentAccessKey::from(*access_key),
+        )?;
+    }
+    Ok(())
+}
+
+/// Advances the nonces of the live access keys, the nonce never goes back
+fn update_current_access_key_nonces(
+    conn: &PgConnection,
+    nonces: &KeyNonces,
+) -> diesel::QueryResult<()> {
+    for ((account_id, public_key), nonce) in nonces {
+        diesel::update(
+            schema::current_access_keys::table
+                .filter(schema::current_access_keys::dsl::account_id.eq(account_id))
+                .filter(schema::current_access_keys::dsl::public_key.eq(public_key))
+                .filter(schema::current_access_keys::dsl::is_deleted.eq(false))
+                .filter(
+                    schema::current_access_keys::dsl::nonce
+                        .is_null()
+                        .or(schema::current_access_keys::dsl::nonce.lt(nonce)),
+                ),
+        )
+        .set(schema::current_access_keys::dsl::nonce.eq(nonce))
+        .execute(conn)?;
+    }
+    Ok(())
+}
+
+/// Transactions signed by the access keys
+fn key_usage(
+    height: near_indexer::near_primitives::types::BlockHeight,
+    chunks: &[near_indexer::IndexerChunkView],
+) -> Vec<AccessKeyUsage> {
+    chunks
+        .iter()
+        .flat_map(|chunk| &chunk.transactions)
+        .map(|transaction| AccessKeyUsage::from_transaction_view(&transaction.transaction, height))
+        .collect()
+}
+
+/// Records the transactions signed by the access keys
+fn insert_key_usage(conn: &PgConnection, key_usage: &[AccessKeyUsage]) -> diesel::QueryResult<()> {
+    if key_usage.is_empty() {
+        return Ok(());
+    }
+    diesel::insert_into(schema::access_key_usage::table)
+        .values(key_usage)
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Collects the nonces of the transactions signed by every access key in the block
+fn transaction_nonces(chunks: &[near_indexer::IndexerChunkView]) -> KeyNonces {
+    let mut nonces = KeyNonces::new();
+    for transaction in chunks
+        .iter()
+        .flat_map(|chunk| &chunk.transactions)
+        .map(|transaction| &transaction.transaction)
+    {
+        let transaction_nonce = BigDecimal::from(transaction.nonce);
+        let nonce = nonces
+            .entry((
+                transaction.signer_id.to_string(),
+                transaction.public_key.to_string(),
+            ))
+            .or_insert_with(|| transaction_nonce.clone());
+        if *nonce < transaction_nonce {
+            *nonce = transaction_nonce;
+        }
+    }
+    nonces
+}
+
+/// Sets the status of the final access key actions of the receipts, the outcomes of the receipts
+/// which are not stored yet are kept as pending outcomes.
+/// Returns the access key actions with updated status
+fn update_receipt_status(
+    conn: &PgConnection,
+    receipt_ids: Vec<String>,
+    status: ExecutionStatus,
+    block_height: &BigDecimal,
+    block_metrics: &mut metrics::BlockMetrics,
+) -> diesel::QueryResult<Vec<AccessKey>> {
+    debug!(target: INDEXER_FOR_WALLET, "update_receipt_status called");
+    if receipt_ids.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let stored_receipt_ids: HashSet<String> = schema::access_keys::table
+        .filter(schema::access_keys::dsl::receipt_hash.eq_any(&receipt_ids))
+        .filter(schema::access_keys::dsl::is_final.eq(true))
+        .select(schema::access_keys::dsl::receipt_hash)
+        .distinct()
+        .load::<String>(conn)?
+        .into_iter()
+        .collect();
+    // The actions of the receipts executed in the same block got the status on insert already
+    let updated_access_keys: Vec<AccessKey> = diesel::update(
+        schema::access_keys::table
+            .filter(schema::access_keys::dsl::receipt_hash.eq_any(&receipt_ids))
+            .filter(schema::access_keys::dsl::is_final.eq(true))
+            .filter(schema::access_keys::dsl::status.eq(ExecutionStatus::Pending)),
+    )
+    .set(schema::access_keys::dsl::status.eq(status))
+    .get_results(conn)?;
+
+    let pending_outcomes: Vec<PendingOutcome> = receipt_ids
+        .iter()
+        .filter(|receipt_id| !stored_receipt_ids.contains(receipt_id.as_str()))
+        .map(|receipt_id| PendingOutcome {
+            receipt_hash: receipt_id.clone(),
+            status,
+            block_height: block_height.clone(),
+        })
+        .collect();
+    block_metrics.add_receipt_status_updates(stored_receipt_ids.len(), pending_outcomes.len());
+    if !pending_outcomes.is_empty() {
         warn!(
             target: INDEXER_FOR_WALLET,
-            "{} of {} update status [{:?}]",
-            rows_touched,
+            "{} of {} receipts are not stored yet, saving [{:?}] status as pending outcomes",
+            pending_outcomes.len(),
             receipt_ids.len(),
             status
         );
+        diesel::insert_into(schema::pending_outcomes::table)
+            .values(&pending_outcomes)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
     }
     debug!(target: INDEXER_FOR_WALLET, "update_receipt_status finished");
+    Ok(updated_access_keys)
 }
 
-async fn handle_outcomes(
-    outcomes: Vec<&near_indexer::IndexerExecutionOutcomeWithReceipt>,
-    pool: &actix_diesel::Database<PgConnection>,
-) {
-    let mut failed_receipt_ids: Vec<String> = vec![];
-    let mut succeeded_receipt_ids: Vec<String> = vec![];
-
+/// Outcomes of the receipts with access key actions. Only such receipts are stored, so we
+/// don't need to update (or keep as pending) the statuses of the others
+fn receipt_outcomes(
+    outcomes: &[&near_indexer::IndexerExecutionOutcomeWithReceipt],
+    block: &near_indexer::near_primitives::views::BlockHeaderView,
+) -> Vec<ReceiptOutcome> {
+    let mut receipt_outcomes: Vec<ReceiptOutcome> = vec![];
     for outcome in outcomes {
+        if let Some(receipt) = &outcome.receipt {
+            // The keys deleted along with the account are unknown here
+            if !db::access_keys::is_delete_account_receipt(receipt)
+                && AccessKey::from_receipt_view(receipt, block, None, &[]).is_empty()
+            {
+                continue;
+            }
+        }
         let status: db::enums::ExecutionStatus =
             outcome.execution_outcome.outcome.status.clone().into();
-        match status {
+        if let db::enums::ExecutionStatus::Pending = status {
+            warn!(
+                target: INDEXER_FOR_WALLET,
+                "ExecutionOutcome status Pending is not expected here ..\n\
+                {:#?}",
+                outcome
+            );
+            continue;
+        }
+        receipt_outcomes.push(ReceiptOutcome {
+            receipt_hash: outcome.execution_outcome.id.to_string(),
+            status,
+        });
+    }
+    receipt_outcomes
+}
+
+/// Returns the access key actions with updated status
+fn handle_outcomes(
+    conn: &PgConnection,
+    outcomes: &[ReceiptOutcome],
+    block_height: &BigDecimal,
+    block_metrics: &mut metrics::BlockMetrics,
+) -> diesel::QueryResult<Vec<AccessKey>> {
+    let mut failed_receipt_ids: Vec<String> = vec![];
+    let mut succeeded_receipt_ids: Vec<String> = vec![];
+    for outcome in outcomes {
+        match outcome.status {
             db::enums::ExecutionStatus::Success => {
-                succeeded_receipt_ids.push(outcome.execution_outcome.id.to_string());
+                succeeded_receipt_ids.push(outcome.receipt_hash.clone());
             }
             db::enums::ExecutionStatus::Failed => {
-                failed_receipt_ids.push(outcome.execution_outcome.id.to_string())
-            }
-            db::enums::ExecutionStatus::Pending => {
-                warn!(
-                    target: INDEXER_FOR_WALLET,
-                    "ExecutionOutcome status Pending is not expected here ..\n\
-                    {:#?}",
-                    outcome
-                );
+                failed_receipt_ids.push(outcome.receipt_hash.clone())
             }
+            db::enums::ExecutionStatus::Pending => {}
         }
     }
 
@@ -287,62 +471,498 @@ async fn handle_outcomes(
         succeeded_receipt_ids.len()
     );
 
-    let update_failed_future =
-        update_receipt_status(failed_receipt_ids, ExecutionStatus::Failed, &pool);
+    let mut updated_access_keys = update_receipt_status(
+        conn,
+        failed_receipt_ids,
+        ExecutionStatus::Failed,
+        block_height,
+        block_metrics,
+    )?;
+    updated_access_keys.extend(update_receipt_status(
+        conn,
+        succeeded_receipt_ids,
+        ExecutionStatus::Success,
+        block_height,
+        block_metrics,
+    )?);
+    Ok(updated_access_keys)
+}
 
-    let update_succeeded_future =
-        update_receipt_status(succeeded_receipt_ids, ExecutionStatus::Success, &pool);
+/// Accounts created and deleted by the successful receipts
+fn account_changes(
+    outcomes: &[&near_indexer::IndexerExecutionOutcomeWithReceipt],
+    block_height: near_indexer::near_primitives::types::BlockHeight,
+) -> Vec<AccountChange> {
+    let mut account_changes: Vec<AccountChange> = vec![];
+    for outcome in outcomes {
+        let receipt = match &outcome.receipt {
+            Some(receipt) => receipt,
+            None => continue,
+        };
+        if !matches!(
+            ExecutionStatus::from(outcome.execution_outcome.outcome.status.clone()),
+            ExecutionStatus::Success
+        ) {
+            continue;
+        }
 
-    join!(update_failed_future, update_succeeded_future);
+        if let Some(account) = Account::created_from_receipt_view(receipt, block_height) {
+            account_changes.push(AccountChange::Created(account));
+        }
+        if db::access_keys::is_delete_account_receipt(receipt) {
+            account_changes.push(AccountChange::Deleted {
+                account_id: receipt.receiver_id.to_string(),
+                receipt_hash: receipt.receipt_id.to_string(),
+                block_height: block_height.into(),
+            });
+        }
+    }
+    account_changes
 }
 
-async fn handle_message(
-    pool: std::sync::Arc<actix_diesel::Database<PgConnection>>,
-    streamer_message: near_indexer::StreamerMessage,
-) {
+/// Records the accounts created and deleted by the final block
+fn apply_account_changes(
+    conn: &PgConnection,
+    account_changes: &[AccountChange],
+) -> diesel::QueryResult<()> {
+    for account_change in account_changes {
+        match account_change {
+            AccountChange::Created(account) => {
+                // The account may be created again after it was deleted
+                diesel::update(
+                    schema::accounts::table
+                        .filter(schema::accounts::dsl::account_id.eq(&account.account_id))
+                        .filter(schema::accounts::dsl::deleted_by_receipt_hash.is_not_null()),
+                )
+                .set((
+                    schema::accounts::dsl::created_by_receipt_hash
+                        .eq(&account.created_by_receipt_hash),
+                    schema::accounts::dsl::created_by_account_id.eq(&account.created_by_account_id),
+                    schema::accounts::dsl::created_at_block_height
+                        .eq(&account.created_at_block_height),
+                    schema::accounts::dsl::deleted_by_receipt_hash.eq(None::<String>),
+                    schema::accounts::dsl::deleted_at_block_height.eq(None::<BigDecimal>),
+                ))
+                .execute(conn)?;
+                // Transfers to the existing implicit accounts don't change anything
+                diesel::insert_into(schema::accounts::table)
+                    .values(account)
+                    .on_conflict_do_nothing()
+                    .execute(conn)?;
+            }
+            AccountChange::Deleted {
+                account_id,
+                receipt_hash,
+                block_height,
+            } => {
+                let mut account = Account::from_state(account_id);
+                account.deleted_by_receipt_hash = Some(receipt_hash.clone());
+                account.deleted_at_block_height = Some(block_height.clone());
+                diesel::insert_into(schema::accounts::table)
+                    .values(&account)
+                    .on_conflict(schema::accounts::dsl::account_id)
+                    .do_update()
+                    .set((
+                        schema::accounts::dsl::deleted_by_receipt_hash
+                            .eq(excluded(schema::accounts::dsl::deleted_by_receipt_hash)),
+                        schema::accounts::dsl::deleted_at_block_height
+                            .eq(excluded(schema::accounts::dsl::deleted_at_block_height)),
+                    ))
+                    .execute(conn)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Applies the staged changes of the block which became final: the outcomes, the accounts,
+/// the nonces and the key usage, along with `current_access_keys`, webhooks and alerts of
+/// the access key actions. Returns the alerts raised by the block
+fn finalize_block(
+    conn: &PgConnection,
+    checkpoint: &IndexerCheckpoint,
+    notify_channel: Option<&str>,
+    alert_rules: &[Box<dyn alerts::AlertRule>],
+    block_metrics: &mut metrics::BlockMetrics,
+) -> diesel::QueryResult<Vec<Alert>> {
+    let changes = match db::staged_changes::take_staged_changes(conn, &checkpoint.block_hash)? {
+        Some(changes) => changes,
+        // The block was stored before its changes were staged
+        None => return Ok(vec![]),
+    };
     info!(
         target: INDEXER_FOR_WALLET,
-        "Block height {:?}", streamer_message.block.header.height
-    );
-    let receipts_future = insert_receipts(
-        streamer_message.block.header.height,
-        &streamer_message.chunks,
-        &pool,
+        "Block #{} ({}) is final, applying its changes",
+        checkpoint.block_height,
+        checkpoint.block_hash
     );
 
-    info!(
-        target: INDEXER_FOR_WALLET,
-        "Handling outcomes, total amount {}",
-        &streamer_message
-            .chunks
+    let mut updated_access_keys = handle_outcomes(
+        conn,
+        &changes.outcomes,
+        &checkpoint.block_height,
+        block_metrics,
+    )?;
+    updated_access_keys.extend(apply_final_pending_outcomes(conn, &checkpoint.block_hash)?);
+    notifications::notify_access_keys(conn, notify_channel, &updated_access_keys)?;
+    let block_access_keys: Vec<AccessKey> = schema::access_keys::table
+        .filter(schema::access_keys::dsl::block_hash.eq(&checkpoint.block_hash))
+        .load(conn)?;
+    forget_pending_outcomes(
+        conn,
+        block_access_keys
             .iter()
-            .map(|chunk| chunk.receipt_execution_outcomes.len())
-            .sum::<usize>()
-    );
-    let outcomes_future = handle_outcomes(
-        streamer_message
+            .map(|access_key| access_key.receipt_hash.clone())
+            .unique()
+            .collect(),
+    )?;
+    // The actions of the block along with the actions of the earlier blocks executed in this one
+    let access_keys: Vec<AccessKey> = block_access_keys
+        .into_iter()
+        .chain(updated_access_keys.into_iter().filter(|access_key| {
+            access_key.block_hash.as_deref() != Some(checkpoint.block_hash.as_str())
+        }))
+        .collect();
+
+    let keys_before = alerts::load_account_keys(conn, &access_keys)?;
+    update_current_access_keys(conn, &access_keys)?;
+    webhooks::enqueue_deliveries(conn, &access_keys)?;
+    let alerts = alerts::evaluate_alerts(conn, alert_rules, &access_keys, &keys_before)?;
+    apply_account_changes(conn, &changes.accounts)?;
+    update_current_access_key_nonces(
+        conn,
+        &changes
+            .nonces
+            .into_iter()
+            .map(|key_nonce| {
+                (
+                    (key_nonce.account_id, key_nonce.public_key),
+                    key_nonce.nonce,
+                )
+            })
+            .collect(),
+    )?;
+    insert_key_usage(conn, &changes.key_usage)?;
+    Ok(alerts)
+}
+
+/// Result of `store_block` which is handled once the transaction is committed, so the retries
+/// neither duplicate the alerts nor count the metrics twice
+#[derive(Default, Debug)]
+struct StoredBlock {
+    /// Alerts raised by the blocks which became final
+    alerts: Vec<Alert>,
+    metrics: metrics::BlockMetrics,
+}
+
+/// Writes the access key actions of the block along with its checkpoint and stages the rest
+/// of its changes until the block is final, so the blocks which never become final are rolled
+/// back without a trace. Applies the changes of the blocks which became final.
+/// Is expected to be called within a transaction to never leave the block half-indexed
+fn store_block(
+    conn: &PgConnection,
+    streamer_message: &near_indexer::StreamerMessage,
+    notify_channel: Option<&str>,
+    alert_rules: &[Box<dyn alerts::AlertRule>],
+) -> diesel::QueryResult<StoredBlock> {
+    let mut stored_block = StoredBlock::default();
+    let block = &streamer_message.block.header;
+    let is_new_block = diesel::insert_into(schema::indexer_checkpoints::table)
+        .values(IndexerCheckpoint::from(block))
+        .on_conflict_do_nothing()
+        .execute(conn)?
+        > 0;
+    if is_new_block {
+        let inserted_access_keys = insert_receipts(conn, block, &streamer_message.chunks)?;
+        notifications::notify_access_keys(conn, notify_channel, &inserted_access_keys)?;
+        stored_block
+            .metrics
+            .add_inserted_access_keys(&inserted_access_keys);
+
+        let outcomes: Vec<&near_indexer::IndexerExecutionOutcomeWithReceipt> = streamer_message
             .chunks
             .iter()
             .flat_map(|chunk| &chunk.receipt_execution_outcomes)
-            .collect(),
-        &pool,
-    );
+            .collect();
+        info!(
+            target: INDEXER_FOR_WALLET,
+            "Handling outcomes, total amount {}",
+            outcomes.len()
+        );
+        let changes = BlockChanges {
+            outcomes: receipt_outcomes(&outcomes, block),
+            accounts: account_changes(&outcomes, block.height),
+            nonces: transaction_nonces(&streamer_message.chunks)
+                .into_iter()
+                .map(|((account_id, public_key), nonce)| KeyNonce {
+                    account_id,
+                    public_key,
+                    nonce,
+                })
+                .collect(),
+            key_usage: key_usage(block.height, &streamer_message.chunks),
+        };
+        diesel::insert_into(schema::staged_changes::table)
+            .values(StagedChanges::new(block, &changes))
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+    } else {
+        info!(
+            target: INDEXER_FOR_WALLET,
+            "Block #{} ({}) is stored already", block.height, block.hash
+        );
+    }
+
+    for checkpoint in finality::apply_finality(conn)? {
+        let alerts = finalize_block(
+            conn,
+            &checkpoint,
+            notify_channel,
+            alert_rules,
+            &mut stored_block.metrics,
+        )?;
+        stored_block.alerts.extend(alerts);
+    }
+    Ok(stored_block)
+}
+
+async fn handle_message(
+    pool: std::sync::Arc<actix_diesel::Database<PgConnection>>,
+    streamer_message: near_indexer::StreamerMessage,
+    notify_channel: Option<String>,
+    alerting: std::sync::Arc<alerts::Alerting>,
+) -> near_indexer::near_primitives::types::BlockHeight {
+    let height = streamer_message.block.header.height;
+    info!(target: INDEXER_FOR_WALLET, "Block height {:?}", height);
+    metrics::IN_FLIGHT_BLOCKS.inc();
+
+    let streamer_message = std::sync::Arc::new(streamer_message);
+    loop {
+        let streamer_message = streamer_message.clone();
+        let notify_channel = notify_channel.clone();
+        let alert_rules = alerting.clone();
+        match pool
+            .get(move |conn| {
+                conn.transaction(|| {
+                    store_block(
+                        conn,
+                        &streamer_message,
+                        notify_channel.as_deref(),
+                        &alert_rules.rules,
+                    )
+                })
+            })
+            .await
+        {
+            Ok(stored_block) => {
+                alerting.emit(&stored_block.alerts);
+                stored_block.metrics.record();
+                break;
+            }
+            Err(async_error) => {
+                error!(
+                    target: INDEXER_FOR_WALLET,
+                    "Failed to store block #{}, retrying in {} milliseconds... \n {:#?}",
+                    height,
+                    INTERVAL.as_millis(),
+                    async_error
+                );
+                metrics::DB_RETRIES.with_label_values(&["store_block"]).inc();
+                time::sleep(INTERVAL).await;
+            }
+        }
+    }
+    metrics::IN_FLIGHT_BLOCKS.dec();
+    metrics::PROCESSED_BLOCKS.inc();
+    height
+}
 
-    join!(receipts_future, outcomes_future);
+/// Reports the receipts which are still PENDING after `stale_pending_blocks` blocks.
+/// The status is compared with a literal, so the partial index of the `PENDING` rows is used
+/// by the generic plan of the prepared statement as well
+async fn report_stale_pending_receipts(
+    height: near_indexer::near_primitives::types::BlockHeight,
+    stale_pending_blocks: u64,
+    pool: &actix_diesel::Database<PgConnection>,
+) {
+    let stale_receipt_ids: Vec<String> = match schema::access_keys::table
+        .filter(diesel::dsl::sql::<diesel::sql_types::Bool>(
+            "status = 'PENDING'",
+        ))
+        .filter(
+            schema::access_keys::dsl::block_height
+                .lt(BigDecimal::from(height.saturating_sub(stale_pending_blocks))),
+        )
+        .select(schema::access_keys::dsl::receipt_hash)
+        .distinct()
+        .load_async(pool)
+        .await
+    {
+        Ok(res) => res,
+        Err(async_error) => {
+            error!(
+                target: INDEXER_FOR_WALLET,
+                "Failed to load stale pending receipts \n {:#?}", async_error
+            );
+            return;
+        }
+    };
+    if !stale_receipt_ids.is_empty() {
+        warn!(
+            target: INDEXER_FOR_WALLET,
+            "{} receipts are PENDING for more than {} blocks, e.g. {:?}",
+            stale_receipt_ids.len(),
+            stale_pending_blocks,
+            stale_receipt_ids.iter().take(10).collect::<Vec<_>>()
+        );
+    }
 }
 
-async fn listen_blocks(stream: mpsc::Receiver<near_indexer::StreamerMessage>) {
-    let pool = std::sync::Arc::new(establish_connection());
+/// Removes the pending outcomes older than `retention_blocks` blocks, their receipts are not
+/// expected to be stored anymore (e.g. they were executed before the indexer started)
+async fn prune_pending_outcomes(
+    height: near_indexer::near_primitives::types::BlockHeight,
+    retention_blocks: u64,
+    pool: &actix_diesel::Database<PgConnection>,
+) {
+    match diesel::delete(
+        schema::pending_outcomes::table.filter(
+            schema::pending_outcomes::dsl::block_height
+                .lt(BigDecimal::from(height.saturating_sub(retention_blocks))),
+        ),
+    )
+    .execute_async(pool)
+    .await
+    {
+        Ok(pruned_amount) => debug!(
+            target: INDEXER_FOR_WALLET,
+            "Pruned {} pending outcomes", pruned_amount
+        ),
+        Err(async_error) => error!(
+            target: INDEXER_FOR_WALLET,
+            "Failed to prune pending outcomes \n {:#?}", async_error
+        ),
+    }
+}
+
+/// Removes the access key usage older than `retention_blocks` blocks
+async fn prune_key_usage(
+    height: near_indexer::near_primitives::types::BlockHeight,
+    retention_blocks: u64,
+    pool: &actix_diesel::Database<PgConnection>,
+) {
+    match diesel::delete(
+        schema::access_key_usage::table.filter(
+            schema::access_key_usage::dsl::block_height
+                .lt(BigDecimal::from(height.saturating_sub(retention_blocks))),
+        ),
+    )
+    .execute_async(pool)
+    .await
+    {
+        Ok(pruned_amount) => debug!(
+            target: INDEXER_FOR_WALLET,
+            "Pruned {} access key usage rows", pruned_amount
+        ),
+        Err(async_error) => error!(
+            target: INDEXER_FOR_WALLET,
+            "Failed to prune access key usage \n {:#?}", async_error
+        ),
+    }
+}
+
+/// Resumes from the end of the contiguous range of processed blocks if there are checkpoints
+/// in the database. Node's interruption point is not used in this case, since it is saved
+/// when the block is sent to the stream, not when it is stored
+async fn resume_sync_mode(
+    pool: &actix_diesel::Database<PgConnection>,
+) -> near_indexer::SyncModeEnum {
+    match pool.get(db::indexer_checkpoints::resume_height).await {
+        Ok(Some(height)) => {
+            info!(
+                target: INDEXER_FOR_WALLET,
+                "Block #{} is the last processed one according to checkpoints, resuming from #{} instead of the node's interruption point",
+                height,
+                height + 1
+            );
+            near_indexer::SyncModeEnum::BlockHeight(height + 1)
+        }
+        Ok(None) => {
+            info!(
+                target: INDEXER_FOR_WALLET,
+                "No checkpoints found, resuming from the node's interruption point"
+            );
+            near_indexer::SyncModeEnum::FromInterruption
+        }
+        Err(async_error) => {
+            error!(
+                target: INDEXER_FOR_WALLET,
+                "Failed to load checkpoints, resuming from the node's interruption point \n {:#?}",
+                async_error
+            );
+            near_indexer::SyncModeEnum::FromInterruption
+        }
+    }
+}
+
+async fn listen_blocks(
+    stream: mpsc::Receiver<near_indexer::StreamerMessage>,
+    pool: std::sync::Arc<actix_diesel::Database<PgConnection>>,
+    ordered: bool,
+    stale_pending_blocks: u64,
+    pending_outcomes_retention_blocks: u64,
+    key_usage_retention_blocks: Option<u64>,
+    notify_channel: Option<String>,
+    alerting: alerts::Alerting,
+) {
     info!(
         target: INDEXER_FOR_WALLET,
-        "NEAR Indexer for Wallet started."
+        "NEAR Indexer for Wallet started{}.",
+        if ordered { " in ordered mode" } else { "" }
     );
 
-    let mut handle_messages = tokio_stream::wrappers::ReceiverStream::new(stream)
-        .map(|streamer_message| handle_message(pool.clone(), streamer_message))
-        .buffer_unordered(100);
+    let alerting = std::sync::Arc::new(alerting);
+    let handle_messages = tokio_stream::wrappers::ReceiverStream::new(stream)
+        .map(|streamer_message| {
+            handle_message(
+                pool.clone(),
+                streamer_message,
+                notify_channel.clone(),
+                alerting.clone(),
+            )
+        });
+    let mut handle_messages = if ordered {
+        // Every block is fully written before the next one is taken from the stream
+        handle_messages.buffered(1).boxed_local()
+    } else {
+        handle_messages.buffer_unordered(100).boxed_local()
+    };
 
-    while let Some(_handled_message) = handle_messages.next().await {}
+    let mut latest_height = 0;
+    let mut last_periodic_tasks_run = std::time::Instant::now();
+    let mut last_rate_measurement = std::time::Instant::now();
+    let mut blocks_since_rate_measurement = 0u32;
+    while let Some(height) = handle_messages.next().await {
+        latest_height = std::cmp::max(latest_height, height);
+        metrics::LAST_PROCESSED_HEIGHT.set(latest_height as i64);
+        blocks_since_rate_measurement += 1;
+        if last_rate_measurement.elapsed() >= BLOCKS_RATE_INTERVAL {
+            metrics::BLOCKS_PER_SECOND.set(
+                f64::from(blocks_since_rate_measurement)
+                    / last_rate_measurement.elapsed().as_secs_f64(),
+            );
+            last_rate_measurement = std::time::Instant::now();
+            blocks_since_rate_measurement = 0;
+        }
+        if last_periodic_tasks_run.elapsed() >= PERIODIC_TASKS_INTERVAL {
+            report_stale_pending_receipts(latest_height, stale_pending_blocks, &pool).await;
+            prune_pending_outcomes(latest_height, pending_outcomes_retention_blocks, &pool).await;
+            if let Some(retention_blocks) = key_usage_retention_blocks {
+                prune_key_usage(latest_height, retention_blocks, &pool).await;
+            }
+            last_periodic_tasks_run = std::time::Instant::now();
+        }
+    }
 }
 
 fn main() {
@@ -365,17 +985,52 @@ fn main() {
         .unwrap_or_else(|| std::path::PathBuf::from(near_indexer::get_default_home()));
 
     match opts.subcmd {
-        SubCommand::Run => {
-            let indexer_config = near_indexer::IndexerConfig {
-                home_dir,
-                sync_mode: near_indexer::SyncModeEnum::FromInterruption,
-                await_for_node_synced: near_indexer::AwaitForNodeSyncedEnum::WaitForFullSync,
-            };
+        SubCommand::Run(args) => {
             let system = actix::System::new();
             system.block_on(async move {
+                let pool = std::sync::Arc::new(establish_connection());
+                let sync_mode = if let Some(height) = args.sync_from_block {
+                    near_indexer::SyncModeEnum::BlockHeight(height)
+                } else if args.sync_from_latest {
+                    near_indexer::SyncModeEnum::LatestSynced
+                } else if args.sync_from_interruption {
+                    near_indexer::SyncModeEnum::FromInterruption
+                } else {
+                    resume_sync_mode(&pool).await
+                };
+                let await_for_node_synced = if args.stream_while_syncing {
+                    near_indexer::AwaitForNodeSyncedEnum::StreamWhileSyncing
+                } else {
+                    near_indexer::AwaitForNodeSyncedEnum::WaitForFullSync
+                };
+                let indexer_config = near_indexer::IndexerConfig {
+                    home_dir,
+                    sync_mode,
+                    await_for_node_synced,
+                };
                 let indexer = near_indexer::Indexer::new(indexer_config);
                 let stream = indexer.streamer();
-                actix::spawn(listen_blocks(stream));
+                actix::spawn(webhooks::deliver_webhooks(pool.clone()));
+                if let Some(metrics_addr) = args.metrics_addr {
+                    actix::spawn(async move {
+                        if let Err(err) = metrics::serve(metrics_addr).await {
+                            error!(
+                                target: INDEXER_FOR_WALLET,
+                                "Metrics server failed: {:?}", err
+                            );
+                        }
+                    });
+                }
+                actix::spawn(listen_blocks(
+                    stream,
+                    pool,
+                    args.ordered,
+                    args.stale_pending_blocks,
+                    args.pending_outcomes_retention_blocks,
+                    args.key_usage_retention_blocks,
+                    args.notify_channel,
+                    alerts::Alerting::new(args.alert_max_existing_keys),
+                ));
             });
             system.run().unwrap();
         }
@@ -390,13 +1045,481 @@ fn main() {
             config.download,
             config.download_genesis_url.as_ref().map(AsRef::as_ref),
         ),
-        SubCommand::DumpState => {
+        SubCommand::DumpState(args) => {
+            let near_config = neard::load_config(&home_dir);
+            actix::run(async move {
+                if let Err(err) =
+                    dump_state::dump_existing_access_keys(home_dir, near_config, args).await
+                {
+                    error!(target: INDEXER_FOR_WALLET, "Failed to dump the state: {}", err);
+                    std::process::exit(1);
+                }
+                actix::System::current().stop();
+            })
+            .unwrap();
+        }
+        SubCommand::LoadDump(args) => {
+            actix::run(async move {
+                if let Err(err) = dump_state::load_dump(args).await {
+                    error!(target: INDEXER_FOR_WALLET, "Failed to load the dump: {}", err);
+                    std::process::exit(1);
+                }
+                actix::System::current().stop();
+            })
+            .unwrap();
+        }
+        SubCommand::Verify(args) => {
             let near_config = neard::load_config(&home_dir);
+            let fail_on_drift = args.fail_on_drift;
             actix::run(async move {
-                dump_existing_access_keys(home_dir, near_config).await;
+                match verify::verify_access_keys(home_dir, near_config, args).await {
+                    Ok(has_drift) => {
+                        if has_drift && fail_on_drift {
+                            std::process::exit(1);
+                        }
+                    }
+                    Err(err) => {
+                        error!(target: INDEXER_FOR_WALLET, "Failed to verify the state: {}", err);
+                        std::process::exit(1);
+                    }
+                }
                 actix::System::current().stop();
             })
             .unwrap();
         }
+        SubCommand::Serve(args) => {
+            actix::run(async move {
+                if let Err(err) = api::serve(&args.bind).await {
+                    error!(target: INDEXER_FOR_WALLET, "HTTP API failed: {:?}", err);
+                }
+                actix::System::current().stop();
+            })
+            .unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use postgres::fallible_iterator::FallibleIterator;
+
+    use super::*;
+    use crate::test_utils;
+
+    const ACCOUNT_ID: &str = "alice.near";
+
+    fn store(conn: &PgConnection, streamer_message: &near_indexer::StreamerMessage) {
+        conn.transaction(|| store_block(conn, streamer_message, None, &[]))
+            .expect("Failed to store block");
+    }
+
+    fn receipt_rows(conn: &PgConnection, receipt_hash: &str) -> Vec<AccessKey> {
+        schema::access_keys::table
+            .filter(schema::access_keys::dsl::receipt_hash.eq(receipt_hash))
+            .load(conn)
+            .unwrap()
+    }
+
+    fn live_keys(conn: &PgConnection) -> Vec<String> {
+        schema::current_access_keys::table
+            .filter(schema::current_access_keys::dsl::account_id.eq(ACCOUNT_ID))
+            .filter(schema::current_access_keys::dsl::is_deleted.eq(false))
+            .select(schema::current_access_keys::dsl::public_key)
+            .order(schema::current_access_keys::dsl::public_key)
+            .load(conn)
+            .unwrap()
+    }
+
+    /// Notifications are delivered on commit, so the block is committed and cleaned up afterwards
+    #[test]
+    #[ignore]
+    fn access_key_changes_are_notified() {
+        const NOTIFY_CHANNEL: &str = "access_keys_test";
+        let conn = test_utils::test_connection();
+        let mut listener =
+            postgres::Client::connect(&std::env::var("DATABASE_URL").unwrap(), postgres::NoTls)
+                .unwrap();
+        listener
+            .batch_execute(&format!("LISTEN {}", NOTIFY_CHANNEL))
+            .unwrap();
+
+        let genesis = test_utils::crypto_hash("genesis");
+        let block = test_utils::block_header("notified", 1, genesis, genesis);
+        let add_k1 = test_utils::action_receipt(
+            "add-k1",
+            ACCOUNT_ID,
+            vec![test_utils::add_full_access_key("k1")],
+        );
+        let streamer_message = test_utils::streamer_message(
+            block.clone(),
+            vec![add_k1.clone()],
+            vec![(add_k1.clone(), true)],
+        );
+        conn.transaction(|| store_block(&conn, &streamer_message, Some(NOTIFY_CHANNEL), &[]))
+            .unwrap();
+
+        let notifications: Vec<postgres::Notification> = listener
+            .notifications()
+            .timeout_iter(Duration::from_secs(1))
+            .collect()
+            .unwrap();
+        diesel::delete(
+            schema::access_keys::table
+                .filter(schema::access_keys::dsl::block_hash.eq(block.hash.to_string())),
+        )
+        .execute(&conn)
+        .unwrap();
+        diesel::delete(
+            schema::staged_changes::table
+                .filter(schema::staged_changes::dsl::block_hash.eq(block.hash.to_string())),
+        )
+        .execute(&conn)
+        .unwrap();
+        diesel::delete(
+            schema::indexer_checkpoints::table
+                .filter(schema::indexer_checkpoints::dsl::block_hash.eq(block.hash.to_string())),
+        )
+        .execute(&conn)
+        .unwrap();
+
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications[0].channel(), NOTIFY_CHANNEL);
+        let payload: serde_json::Value = serde_json::from_str(notifications[0].payload()).unwrap();
+        assert_eq!(
+            payload,
+            serde_json::json!({
+                "account_id": ACCOUNT_ID,
+                "public_key": test_utils::public_key("k1").to_string(),
+                "action": "ADD",
+                "status": "SUCCESS",
+                "receipt_hash": add_k1.receipt_id.to_string(),
+                "block_height": "1",
+            })
+        );
+    }
+
+    /// The outcome is saved as pending by another block after the block with the receipt
+    /// was stored, the row gets the status once its block is final
+    #[test]
+    #[ignore]
+    fn pending_outcome_is_applied_once_block_is_final() {
+        let conn = test_utils::test_connection();
+        conn.begin_test_transaction().unwrap();
+
+        let genesis = test_utils::crypto_hash("genesis");
+        let b1 = test_utils::block_header("b1", 1, genesis, genesis);
+        let b2 = test_utils::block_header("b2", 2, b1.hash, b1.hash);
+        let add_k1 = test_utils::action_receipt(
+            "add-k1",
+            ACCOUNT_ID,
+            vec![test_utils::add_full_access_key("k1")],
+        );
+        let receipt_hash = add_k1.receipt_id.to_string();
+
+        store(
+            &conn,
+            &test_utils::streamer_message(b1.clone(), vec![add_k1], vec![]),
+        );
+        assert!(matches!(
+            receipt_rows(&conn, &receipt_hash)[0].status,
+            ExecutionStatus::Pending
+        ));
+        diesel::insert_into(schema::pending_outcomes::table)
+            .values(PendingOutcome {
+                receipt_hash: receipt_hash.clone(),
+                status: ExecutionStatus::Success,
+                block_height: 1.into(),
+            })
+            .execute(&conn)
+            .unwrap();
+
+        store(&conn, &test_utils::streamer_message(b2, vec![], vec![]));
+        assert!(matches!(
+            receipt_rows(&conn, &receipt_hash)[0].status,
+            ExecutionStatus::Success
+        ));
+        assert_eq!(
+            live_keys(&conn),
+            vec![test_utils::public_key("k1").to_string()]
+        );
+        let pending_outcomes: i64 = schema::pending_outcomes::table
+            .count()
+            .get_result(&conn)
+            .unwrap();
+        assert_eq!(pending_outcomes, 0);
+    }
+
+    /// The key added by the receipt which deletes the account is deleted along with the
+    /// other keys, its `ADD` and `DELETE` rows have the same height
+    #[test]
+    #[ignore]
+    fn key_added_before_delete_account_is_deleted() {
+        let conn = test_utils::test_connection();
+        conn.begin_test_transaction().unwrap();
+
+        let genesis = test_utils::crypto_hash("genesis");
+        let b1 = test_utils::block_header("b1", 1, genesis, genesis);
+        let b2 = test_utils::block_header("b2", 2, b1.hash, b1.hash);
+        let b3 = test_utils::block_header("b3", 3, b2.hash, b2.hash);
+        let add_k1 = test_utils::action_receipt(
+            "add-k1",
+            ACCOUNT_ID,
+            vec![test_utils::add_full_access_key("k1")],
+        );
+        let add_k2_and_delete_account = test_utils::action_receipt(
+            "add-k2-and-delete-account",
+            ACCOUNT_ID,
+            vec![
+                test_utils::add_full_access_key("k2"),
+                test_utils::delete_account(),
+            ],
+        );
+
+        store(
+            &conn,
+            &test_utils::streamer_message(b1, vec![add_k1.clone()], vec![(add_k1, true)]),
+        );
+        store(
+            &conn,
+            &test_utils::streamer_message(
+                b2,
+                vec![add_k2_and_delete_account.clone()],
+                vec![(add_k2_and_delete_account.clone(), true)],
+            ),
+        );
+        store(&conn, &test_utils::streamer_message(b3, vec![], vec![]));
+
+        assert!(live_keys(&conn).is_empty());
+        let k2: CurrentAccessKey = schema::current_access_keys::table
+            .filter(schema::current_access_keys::dsl::account_id.eq(ACCOUNT_ID))
+            .filter(
+                schema::current_access_keys::dsl::public_key
+                    .eq(test_utils::public_key("k2").to_string()),
+            )
+            .first(&conn)
+            .unwrap();
+        assert!(k2.is_deleted);
+        assert_eq!(
+            k2.receipt_hash,
+            add_k2_and_delete_account.receipt_id.to_string()
+        );
+        assert_eq!(k2.action_index, 1);
+    }
+
+    /// B1 <- B2 <- B3 <- B4 is the final chain, B2' is a fork of B2 which includes the same
+    /// receipt as B2 and deletes the account. B3 finalizes B1 and B4 finalizes B2
+    #[test]
+    #[ignore]
+    fn fork_is_rolled_back_without_a_trace() {
+        let conn = test_utils::test_connection();
+        conn.begin_test_transaction().unwrap();
+
+        let genesis = test_utils::crypto_hash("genesis");
+        let b1 = test_utils::block_header("b1", 1, genesis, genesis);
+        let b2 = test_utils::block_header("b2", 2, b1.hash, genesis);
+        let fork_b2 = test_utils::block_header("fork-b2", 2, b1.hash, genesis);
+        let b3 = test_utils::block_header("b3", 3, b2.hash, b1.hash);
+        let b4 = test_utils::block_header("b4", 4, b3.hash, b2.hash);
+
+        let add_k1 = test_utils::action_receipt(
+            "add-k1",
+            ACCOUNT_ID,
+            vec![test_utils::add_full_access_key("k1")],
+        );
+        let add_k2 = test_utils::action_receipt(
+            "add-k2",
+            ACCOUNT_ID,
+            vec![test_utils::add_full_access_key("k2")],
+        );
+        let delete_account = test_utils::action_receipt(
+            "delete-account",
+            ACCOUNT_ID,
+            vec![test_utils::delete_account()],
+        );
+        let k1 = test_utils::public_key("k1").to_string();
+        let k2 = test_utils::public_key("k2").to_string();
+
+        store(
+            &conn,
+            &test_utils::streamer_message(
+                b1.clone(),
+                vec![add_k1.clone()],
+                vec![(add_k1.clone(), true)],
+            ),
+        );
+        store(
+            &conn,
+            &test_utils::streamer_message(
+                b2.clone(),
+                vec![add_k2.clone()],
+                vec![(add_k2.clone(), true)],
+            ),
+        );
+        store(
+            &conn,
+            &test_utils::streamer_message(
+                fork_b2.clone(),
+                vec![add_k2.clone(), delete_account.clone()],
+                vec![(add_k2.clone(), true), (delete_account.clone(), true)],
+            ),
+        );
+
+        // Every block has its own rows of the same receipt
+        let mut add_k2_blocks: Vec<Option<String>> =
+            receipt_rows(&conn, &add_k2.receipt_id.to_string())
+                .into_iter()
+                .map(|access_key| access_key.block_hash)
+                .collect();
+        add_k2_blocks.sort();
+        let mut expected_blocks = vec![Some(b2.hash.to_string()), Some(fork_b2.hash.to_string())];
+        expected_blocks.sort();
+        assert_eq!(add_k2_blocks, expected_blocks);
+        // The keys added by the blocks which are not final yet are deleted along with the account
+        assert_eq!(
+            receipt_rows(&conn, &delete_account.receipt_id.to_string()).len(),
+            2
+        );
+        // Nothing is applied before the blocks are final
+        assert!(live_keys(&conn).is_empty());
+
+        store(
+            &conn,
+            &test_utils::streamer_message(b3.clone(), vec![], vec![]),
+        );
+        assert_eq!(live_keys(&conn), vec![k1.clone()]);
+
+        store(
+            &conn,
+            &test_utils::streamer_message(b4.clone(), vec![], vec![]),
+        );
+
+        let checkpoints: Vec<IndexerCheckpoint> = schema::indexer_checkpoints::table
+            .order(schema::indexer_checkpoints::dsl::block_height)
+            .load(&conn)
+            .unwrap();
+        assert_eq!(
+            checkpoints
+                .iter()
+                .map(|checkpoint| (checkpoint.block_hash.clone(), checkpoint.is_final))
+                .collect::<Vec<_>>(),
+            vec![
+                (b1.hash.to_string(), true),
+                (b2.hash.to_string(), true),
+                (b3.hash.to_string(), false),
+                (b4.hash.to_string(), false),
+            ]
+        );
+
+        let add_k2_rows = receipt_rows(&conn, &add_k2.receipt_id.to_string());
+        assert_eq!(add_k2_rows.len(), 1);
+        assert_eq!(add_k2_rows[0].block_hash, Some(b2.hash.to_string()));
+        assert!(add_k2_rows[0].is_final);
+        assert!(receipt_rows(&conn, &delete_account.receipt_id.to_string()).is_empty());
+
+        let mut expected_keys = vec![k1, k2];
+        expected_keys.sort();
+        assert_eq!(live_keys(&conn), expected_keys);
+        let deleted_accounts: i64 = schema::accounts::table
+            .filter(schema::accounts::dsl::account_id.eq(ACCOUNT_ID))
+            .filter(schema::accounts::dsl::deleted_by_receipt_hash.is_not_null())
+            .count()
+            .get_result(&conn)
+            .unwrap();
+        assert_eq!(deleted_accounts, 0);
+
+        let staged_blocks: Vec<String> = schema::staged_changes::table
+            .select(schema::staged_changes::dsl::block_hash)
+            .order(schema::staged_changes::dsl::block_height)
+            .load(&conn)
+            .unwrap();
+        assert_eq!(
+            staged_blocks,
+            vec![b3.hash.to_string(), b4.hash.to_string()]
+        );
+    }
+
+    /// B2 <- B3 <- B4 is the final chain, B3 and B4 are stored before B2, so B3 is final
+    /// before its parent is stored. B2 is not a fork and becomes final once it's stored,
+    /// B3' is a fork at the final height and is rolled back
+    #[test]
+    #[ignore]
+    fn final_block_stored_out_of_order_is_not_rolled_back() {
+        let conn = test_utils::test_connection();
+        conn.begin_test_transaction().unwrap();
+
+        let genesis = test_utils::crypto_hash("genesis");
+        let b1 = test_utils::crypto_hash("b1");
+        let b2 = test_utils::block_header("b2", 2, b1, genesis);
+        let b3 = test_utils::block_header("b3", 3, b2.hash, genesis);
+        let fork_b3 = test_utils::block_header("fork-b3", 3, b2.hash, genesis);
+        let b4 = test_utils::block_header("b4", 4, b3.hash, b3.hash);
+
+        let add_k2 = test_utils::action_receipt(
+            "add-k2",
+            ACCOUNT_ID,
+            vec![test_utils::add_full_access_key("k2")],
+        );
+        let add_k3 = test_utils::action_receipt(
+            "add-k3",
+            ACCOUNT_ID,
+            vec![test_utils::add_full_access_key("k3")],
+        );
+
+        store(
+            &conn,
+            &test_utils::streamer_message(b3.clone(), vec![], vec![]),
+        );
+        store(
+            &conn,
+            &test_utils::streamer_message(b4.clone(), vec![], vec![]),
+        );
+        store(
+            &conn,
+            &test_utils::streamer_message(
+                b2.clone(),
+                vec![add_k2.clone()],
+                vec![(add_k2.clone(), true)],
+            ),
+        );
+        store(
+            &conn,
+            &test_utils::streamer_message(
+                fork_b3.clone(),
+                vec![add_k3.clone()],
+                vec![(add_k3.clone(), true)],
+            ),
+        );
+
+        let checkpoints: Vec<IndexerCheckpoint> = schema::indexer_checkpoints::table
+            .order(schema::indexer_checkpoints::dsl::block_height)
+            .load(&conn)
+            .unwrap();
+        assert_eq!(
+            checkpoints
+                .iter()
+                .map(|checkpoint| (checkpoint.block_hash.clone(), checkpoint.is_final))
+                .collect::<Vec<_>>(),
+            vec![
+                (b2.hash.to_string(), true),
+                (b3.hash.to_string(), true),
+                (b4.hash.to_string(), false),
+            ]
+        );
+
+        let add_k2_rows = receipt_rows(&conn, &add_k2.receipt_id.to_string());
+        assert_eq!(add_k2_rows.len(), 1);
+        assert_eq!(add_k2_rows[0].block_hash, Some(b2.hash.to_string()));
+        assert!(add_k2_rows[0].is_final);
+        assert!(receipt_rows(&conn, &add_k3.receipt_id.to_string()).is_empty());
+        assert_eq!(
+            live_keys(&conn),
+            vec![test_utils::public_key("k2").to_string()]
+        );
+
+        let staged_blocks: Vec<String> = schema::staged_changes::table
+            .select(schema::staged_changes::dsl::block_hash)
+            .load(&conn)
+            .unwrap();
+        assert_eq!(staged_blocks, vec![b4.hash.to_string()]);
     }
 }
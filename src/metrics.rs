@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+
+use actix_web::{get, App, HttpResponse, HttpServer};
+use lazy_static::lazy_static;
+use prometheus::{
+    register_gauge, register_int_counter, register_int_counter_vec, register_int_gauge, Encoder,
+    Gauge, IntCounter, IntCounterVec, IntGauge, TextEncoder,
+};
+use tracing::{error, info};
+
+use crate::db::enums::{AccessKeyAction, AccessKeyPermission};
+use crate::db::AccessKey;
+use crate::INDEXER_FOR_WALLET;
+
+lazy_static! {
+    pub(crate) static ref LAST_PROCESSED_HEIGHT: IntGauge = register_int_gauge!(
+        "indexer_for_wallet_last_processed_block_height",
+        "The highest stored block height"
+    )
+    .unwrap();
+    pub(crate) static ref PROCESSED_BLOCKS: IntCounter = register_int_counter!(
+        "indexer_for_wallet_processed_blocks_total",
+        "Amount of the stored blocks"
+    )
+    .unwrap();
+    pub(crate) static ref BLOCKS_PER_SECOND: Gauge = register_gauge!(
+        "indexer_for_wallet_blocks_per_second",
+        "Amount of the blocks stored per second during the last measurement interval"
+    )
+    .unwrap();
+    pub(crate) static ref IN_FLIGHT_BLOCKS: IntGauge = register_int_gauge!(
+        "indexer_for_wallet_in_flight_blocks",
+        "Amount of the blocks being stored concurrently"
+    )
+    .unwrap();
+    pub(crate) static ref INSERTED_ACCESS_KEYS: IntCounterVec = register_int_counter_vec!(
+        "indexer_for_wallet_inserted_access_keys_total",
+        "Amount of the access key actions inserted from the receipts",
+        &["action", "permission"]
+    )
+    .unwrap();
+    pub(crate) static ref RECEIPT_STATUS_UPDATES: IntCounterVec = register_int_counter_vec!(
+        "indexer_for_wallet_receipt_status_updates_total",
+        "Amount of the receipt outcomes which matched the stored access keys or were saved as pending outcomes",
+        &["result"]
+    )
+    .unwrap();
+    pub(crate) static ref DB_RETRIES: IntCounterVec = register_int_counter_vec!(
+        "indexer_for_wallet_db_retries_total",
+        "Amount of the retried database operations",
+        &["operation"]
+    )
+    .unwrap();
+}
+
+fn action_label(action: &AccessKeyAction) -> &'static str {
+    match action {
+        AccessKeyAction::Add => "ADD",
+        AccessKeyAction::Delete => "DELETE",
+    }
+}
+
+fn permission_label(permission: &AccessKeyPermission) -> &'static str {
+    match permission {
+        AccessKeyPermission::NotApplicable => "NOT_APPLICABLE",
+        AccessKeyPermission::FullAccess => "FULL_ACCESS",
+        AccessKeyPermission::FunctionCall => "FUNCTION_CALL",
+    }
+}
+
+/// Counters of the stored block. They are recorded once the transaction of the block is
+/// committed, so the retried transactions are not counted
+#[derive(Default, Debug)]
+pub(crate) struct BlockMetrics {
+    /// Inserted access key actions by action and permission labels
+    inserted_access_keys: HashMap<(&'static str, &'static str), u64>,
+    matched_outcomes: u64,
+    unmatched_outcomes: u64,
+}
+
+impl BlockMetrics {
+    pub fn add_inserted_access_keys(&mut self, access_keys: &[AccessKey]) {
+        for access_key in access_keys {
+            *self
+                .inserted_access_keys
+                .entry((
+                    action_label(&access_key.action),
+                    permission_label(&access_key.permission),
+                ))
+                .or_default() += 1;
+        }
+    }
+
+    pub fn add_receipt_status_updates(&mut self, matched: usize, unmatched: usize) {
+        self.matched_outcomes += matched as u64;
+        self.unmatched_outcomes += unmatched as u64;
+    }
+
+    pub fn record(&self) {
+        for ((action, permission), count) in &self.inserted_access_keys {
+            INSERTED_ACCESS_KEYS
+                .with_label_values(&[*action, *permission])
+                .inc_by(*count);
+        }
+        RECEIPT_STATUS_UPDATES
+            .with_label_values(&["matched"])
+            .inc_by(self.matched_outcomes);
+        RECEIPT_STATUS_UPDATES
+            .with_label_values(&["unmatched"])
+            .inc_by(self.unmatched_outcomes);
+    }
+}
+
+#[get("/metrics")]
+async fn metrics() -> HttpResponse {
+    let encoder = TextEncoder::new();
+    let mut buffer = vec![];
+    match encoder.encode(&prometheus::gather(), &mut buffer) {
+        Ok(()) => HttpResponse::Ok()
+            .content_type(encoder.format_type())
+            .body(buffer),
+        Err(err) => {
+            error!(
+                target: INDEXER_FOR_WALLET,
+                "Failed to encode metrics: {:?}", err
+            );
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Starts HTTP server exposing the metrics in Prometheus format on `/metrics`
+pub(crate) async fn serve(bind: String) -> std::io::Result<()> {
+    info!(
+        target: INDEXER_FOR_WALLET,
+        "Metrics are exposed on http://{}/metrics", bind
+    );
+    HttpServer::new(|| App::new().service(metrics))
+        .workers(1)
+        .bind(bind)?
+        .run()
+        .await
+}
@@ -0,0 +1,54 @@
+use bigdecimal::BigDecimal;
+use diesel::sql_types::Text;
+use diesel::{PgConnection, RunQueryDsl};
+use serde::Serialize;
+
+use crate::db::enums::{AccessKeyAction, ExecutionStatus};
+use crate::db::AccessKey;
+
+/// Payload of the notification sent on every inserted access key action and status update
+#[derive(Serialize)]
+struct AccessKeyNotification<'a> {
+    account_id: &'a str,
+    public_key: &'a str,
+    action: &'a AccessKeyAction,
+    status: ExecutionStatus,
+    receipt_hash: &'a str,
+    block_height: &'a BigDecimal,
+}
+
+impl<'a> From<&'a AccessKey> for AccessKeyNotification<'a> {
+    fn from(access_key: &'a AccessKey) -> Self {
+        Self {
+            account_id: &access_key.account_id,
+            public_key: &access_key.public_key,
+            action: &access_key.action,
+            status: access_key.status,
+            receipt_hash: &access_key.receipt_hash,
+            block_height: &access_key.block_height,
+        }
+    }
+}
+
+/// Sends `pg_notify` with JSON payload for every access key action. Notifications are delivered
+/// to the listeners of the channel once the transaction is committed, so they never see the
+/// changes of the blocks which failed to be stored
+pub(crate) fn notify_access_keys(
+    conn: &PgConnection,
+    channel: Option<&str>,
+    access_keys: &[AccessKey],
+) -> diesel::QueryResult<()> {
+    let channel = match channel {
+        Some(channel) => channel,
+        None => return Ok(()),
+    };
+    for access_key in access_keys {
+        let payload = serde_json::to_string(&AccessKeyNotification::from(access_key))
+            .expect("Notification is always serializable");
+        diesel::sql_query("SELECT pg_notify($1, $2)")
+            .bind::<Text, _>(channel)
+            .bind::<Text, _>(payload)
+            .execute(conn)?;
+    }
+    Ok(())
+}
@@ -2,7 +2,20 @@ table! {
     use diesel::sql_types::*;
     use crate::db::enums::*;
 
-    access_keys (public_key, account_id, action, receipt_hash) {
+    access_key_usage (transaction_hash) {
+        transaction_hash -> Text,
+        account_id -> Text,
+        public_key -> Text,
+        receiver_id -> Text,
+        block_height -> Numeric,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::db::enums::*;
+
+    access_keys (public_key, account_id, action, receipt_hash, block_hash) {
         public_key -> Text,
         account_id -> Text,
         action -> Access_key_action_type,
@@ -10,5 +23,172 @@ table! {
         receipt_hash -> Text,
         block_height -> Numeric,
         permission -> Access_key_permission_type,
+        allowance -> Nullable<Numeric>,
+        receiver_id -> Nullable<Text>,
+        method_names -> Nullable<Array<Text>>,
+        nonce -> Nullable<Numeric>,
+        block_hash -> Nullable<Text>,
+        block_timestamp -> Nullable<Numeric>,
+        is_final -> Bool,
+        action_index -> Int4,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::db::enums::*;
+
+    accounts (account_id) {
+        account_id -> Text,
+        is_implicit -> Bool,
+        created_by_receipt_hash -> Nullable<Text>,
+        created_by_account_id -> Nullable<Text>,
+        created_at_block_height -> Nullable<Numeric>,
+        deleted_by_receipt_hash -> Nullable<Text>,
+        deleted_at_block_height -> Nullable<Numeric>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::db::enums::*;
+
+    alerts (rule, account_id, public_key, receipt_hash) {
+        rule -> Text,
+        account_id -> Text,
+        public_key -> Text,
+        receipt_hash -> Text,
+        block_height -> Numeric,
+        block_timestamp -> Nullable<Numeric>,
+        message -> Text,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::db::enums::*;
+
+    current_access_keys (account_id, public_key) {
+        account_id -> Text,
+        public_key -> Text,
+        permission -> Access_key_permission_type,
+        allowance -> Nullable<Numeric>,
+        receiver_id -> Nullable<Text>,
+        method_names -> Nullable<Array<Text>>,
+        receipt_hash -> Text,
+        block_height -> Numeric,
+        nonce -> Nullable<Numeric>,
+        is_deleted -> Bool,
+        action_index -> Int4,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::db::enums::*;
+
+    indexer_checkpoints (block_hash) {
+        block_height -> Numeric,
+        block_hash -> Text,
+        prev_block_hash -> Text,
+        block_timestamp -> Numeric,
+        is_final -> Bool,
+        last_final_block_hash -> Nullable<Text>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::db::enums::*;
+
+    pending_outcomes (receipt_hash) {
+        receipt_hash -> Text,
+        status -> Execution_status_type,
+        block_height -> Numeric,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::db::enums::*;
+
+    staged_changes (block_hash) {
+        block_hash -> Text,
+        block_height -> Numeric,
+        changes -> Jsonb,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::db::enums::*;
+
+    state_dump_keys (account_id, public_key) {
+        account_id -> Text,
+        public_key -> Text,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::db::enums::*;
+
+    state_dumps (block_height) {
+        block_height -> Numeric,
+        block_hash -> Text,
+        block_timestamp -> Numeric,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::db::enums::*;
+
+    webhook_deliveries (id) {
+        id -> Int8,
+        webhook_id -> Int4,
+        receipt_hash -> Text,
+        account_id -> Text,
+        public_key -> Text,
+        action -> Access_key_action_type,
+        payload -> Text,
+        status -> Webhook_delivery_status_type,
+        attempts -> Int4,
+        next_attempt_at -> Int8,
+        response_status -> Nullable<Int4>,
+        last_error -> Nullable<Text>,
+        delivered_at -> Nullable<Int8>,
     }
 }
+
+table! {
+    use diesel::sql_types::*;
+    use crate::db::enums::*;
+
+    webhooks (id) {
+        id -> Int4,
+        url -> Text,
+        secret -> Text,
+        account_id -> Nullable<Text>,
+        public_key -> Nullable<Text>,
+        action -> Nullable<Access_key_action_type>,
+        permission -> Nullable<Access_key_permission_type>,
+    }
+}
+
+joinable!(webhook_deliveries -> webhooks (webhook_id));
+
+allow_tables_to_appear_in_same_query!(
+    access_key_usage,
+    access_keys,
+    accounts,
+    alerts,
+    current_access_keys,
+    indexer_checkpoints,
+    pending_outcomes,
+    staged_changes,
+    state_dump_keys,
+    state_dumps,
+    webhook_deliveries,
+    webhooks,
+);
@@ -1,15 +1,17 @@
-use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 
+use tracing::info;
+
 use near_chain::{ChainStore, ChainStoreAccess, RuntimeAdapter};
-use near_chain_configs::{Genesis, GenesisConfig};
 use near_indexer::near_primitives::block_header::BlockHeader;
 use near_indexer::near_primitives::state_record::StateRecord;
-use near_indexer::near_primitives::types::{AccountInfo, BlockHeight, StateRoot};
+use near_indexer::near_primitives::types::{BlockHeight, ShardId, StateRoot};
 use near_store::{Store, TrieIterator};
 use neard::NightshadeRuntime;
 
-#[allow(unused)]
+use crate::INDEXER_FOR_WALLET;
+
 pub(crate) enum LoadTrieMode {
     /// Load latest state
     Latest,
@@ -19,12 +21,14 @@ pub(crate) enum LoadTrieMode {
     LastFinalFromHeight(BlockHeight),
 }
 
+/// Returns an error if there is no block to load the state at, e.g. the requested height
+/// was skipped by the chain
 pub(crate) fn load_trie_stop_at_height(
     store: Arc<Store>,
     home_dir: &std::path::Path,
     near_config: &near_indexer::NearConfig,
     mode: LoadTrieMode,
-) -> (NightshadeRuntime, Vec<StateRoot>, BlockHeader) {
+) -> Result<(NightshadeRuntime, Vec<StateRoot>, BlockHeader), String> {
     let mut chain_store = ChainStore::new(store.clone(), near_config.genesis.config.genesis_height);
 
     let runtime = NightshadeRuntime::new(
@@ -34,14 +38,19 @@ pub(crate) fn load_trie_stop_at_height(
         near_config.client_config.tracked_accounts.clone(),
         near_config.client_config.tracked_shards.clone(),
     );
-    let head = chain_store.head().unwrap();
+    let head = chain_store
+        .head()
+        .map_err(|err| format!("Failed to read the chain head: {}", err))?;
     let last_block = match mode {
         LoadTrieMode::LastFinalFromHeight(height) => {
             // find the first final block whose height is at least `height`.
             let mut cur_height = height + 1;
             loop {
                 if cur_height >= head.height {
-                    panic!("No final block with height >= {} exists", height);
+                    return Err(format!(
+                        "No final block with height >= {} exists, the chain head is #{}",
+                        height, head.height
+                    ));
                 }
                 let cur_block_hash = match chain_store.get_block_hash_by_height(cur_height) {
                     Ok(hash) => hash,
@@ -52,9 +61,13 @@ pub(crate) fn load_trie_stop_at_height(
                 };
                 let last_final_block_hash = *chain_store
                     .get_block_header(&cur_block_hash)
-                    .unwrap()
+                    .map_err(|err| format!("Failed to read block #{}: {}", cur_height, err))?
                     .last_final_block();
-                let last_final_block = chain_store.get_block(&last_final_block_hash).unwrap();
+                let last_final_block = chain_store
+                    .get_block(&last_final_block_hash)
+                    .map_err(|err| {
+                        format!("Failed to read block {}: {}", last_final_block_hash, err)
+                    })?;
                 if last_final_block.header().height() >= height {
                     break last_final_block.clone();
                 } else {
@@ -64,12 +77,32 @@ pub(crate) fn load_trie_stop_at_height(
             }
         }
         LoadTrieMode::Height(height) => {
-            let block_hash = chain_store.get_block_hash_by_height(height).unwrap();
-            chain_store.get_block(&block_hash).unwrap().clone()
+            let block_hash = match chain_store.get_block_hash_by_height(height) {
+                Ok(hash) => hash,
+                Err(err) => {
+                    let next_height = (height + 1..=head.height).find(|next_height| {
+                        chain_store.get_block_hash_by_height(*next_height).is_ok()
+                    });
+                    return Err(match next_height {
+                        Some(next_height) => format!(
+                            "No block at height {} ({}), the next existing block is #{}",
+                            height, err, next_height
+                        ),
+                        None => format!(
+                            "No block at height {} ({}), the chain head is #{}",
+                            height, err, head.height
+                        ),
+                    });
+                }
+            };
+            chain_store
+                .get_block(&block_hash)
+                .map_err(|err| format!("Failed to read block #{}: {}", height, err))?
+                .clone()
         }
         LoadTrieMode::Latest => chain_store
             .get_block(&head.last_block_hash)
-            .unwrap()
+            .map_err(|err| format!("Failed to read the head block: {}", err))?
             .clone(),
     };
     let state_roots = last_block
@@ -77,67 +110,237 @@ pub(crate) fn load_trie_stop_at_height(
         .iter()
         .map(|chunk| chunk.prev_state_root())
         .collect();
-    (runtime, state_roots, last_block.header().clone())
+    Ok((runtime, state_roots, last_block.header().clone()))
 }
 
-pub(crate) fn state_dump(
+/// Trie key prefixes as defined in `near_primitives::trie_key::col`. Every trie key starts
+/// with the column byte, so the entries of a column are contiguous in the trie
+pub(crate) mod col {
+    pub const ACCOUNT: u8 = 0;
+    pub const ACCESS_KEY: u8 = 2;
+}
+
+/// Every this amount of scanned trie entries the progress of the shard is logged
+const PROGRESS_REPORT_ENTRIES: u64 = 1_000_000;
+
+/// Batch of the state records or the error which stopped the reading. The channel is closed
+/// once the state is read, so the receivers can tell the end of the state from a failure
+pub(crate) type StateRecordsBatch = Result<Vec<StateRecord>, String>;
+
+/// Iterates over the tries of all the shards and sends the records of the given `columns`
+/// to `sender` in batches of `batch_size`. Up to `jobs` shards are read in parallel, each
+/// on its own thread. Nothing but the current batches is kept in memory, so the memory
+/// usage is bounded by the channel capacity regardless of the state size.
+/// If a shard can't be read, the error is sent instead of the rest of the records
+pub(crate) fn stream_state_records(
     runtime: NightshadeRuntime,
     state_roots: Vec<StateRoot>,
-    last_block_header: BlockHeader,
-    genesis_config: &GenesisConfig,
-) -> Genesis {
-    println!(
-        "Generating genesis from state data of #{} / {}",
-        last_block_header.height(),
-        last_block_header.hash()
-    );
-    let genesis_height = last_block_header.height() + 1;
-    let block_producers = runtime
-        .get_epoch_block_producers_ordered(&last_block_header.epoch_id(), last_block_header.hash())
-        .unwrap();
-    let validators = block_producers
-        .into_iter()
-        .filter_map(|(info, is_slashed)| {
-            if !is_slashed {
-                Some((info.account_id, (info.public_key, info.stake)))
-            } else {
-                None
-            }
-        })
-        .collect::<HashMap<_, _>>();
-
-    let mut records = vec![];
-    for (shard_id, state_root) in state_roots.iter().enumerate() {
-        let trie = runtime.get_trie_for_shard(shard_id as u64);
-        let trie = TrieIterator::new(&trie, &state_root).unwrap();
-        for item in trie {
-            let (key, value) = item.unwrap();
-            if let Some(mut sr) = StateRecord::from_raw_key_value(key, value) {
-                if let StateRecord::Account {
-                    account_id,
-                    account,
-                } = &mut sr
-                {
-                    if account.locked() > 0 {
-                        let stake = *validators.get(account_id).map(|(_, s)| s).unwrap_or(&0);
-                        account.set_amount(account.amount() + account.locked() - stake);
-                        account.set_locked(stake);
+    columns: &[u8],
+    full_scan: bool,
+    jobs: usize,
+    batch_size: usize,
+    sender: tokio::sync::mpsc::Sender<StateRecordsBatch>,
+) {
+    let started_at = std::time::Instant::now();
+    let jobs = std::cmp::max(1, std::cmp::min(jobs, state_roots.len()));
+    let runtime = Arc::new(runtime);
+    let state_roots = Arc::new(state_roots);
+    let columns = Arc::new(columns.to_vec());
+    let next_shard_id = Arc::new(AtomicUsize::new(0));
+    let entries_scanned = Arc::new(AtomicU64::new(0));
+    let records_found = Arc::new(AtomicU64::new(0));
+
+    let workers: Vec<_> = (0..jobs)
+        .map(|_| {
+            let runtime = runtime.clone();
+            let state_roots = state_roots.clone();
+            let columns = columns.clone();
+            let next_shard_id = next_shard_id.clone();
+            let entries_scanned = entries_scanned.clone();
+            let records_found = records_found.clone();
+            let sender = sender.clone();
+            std::thread::spawn(move || loop {
+                let shard_id = next_shard_id.fetch_add(1, Ordering::SeqCst);
+                let state_root = match state_roots.get(shard_id) {
+                    Some(state_root) => state_root,
+                    None => break,
+                };
+                let shard_stats = stream_shard_records(
+                    &runtime,
+                    shard_id as ShardId,
+                    state_root,
+                    &columns,
+                    full_scan,
+                    batch_size,
+                    &sender,
+                );
+                match shard_stats {
+                    Ok(Some((shard_entries_scanned, shard_records_found))) => {
+                        entries_scanned.fetch_add(shard_entries_scanned, Ordering::SeqCst);
+                        records_found.fetch_add(shard_records_found, Ordering::SeqCst);
+                    }
+                    Ok(None) => break,
+                    Err(err) => {
+                        // The receiver may be gone already, there is nobody to report to then
+                        let _ = sender.blocking_send(Err(format!(
+                            "Failed to read the state of shard #{}: {}",
+                            shard_id, err
+                        )));
+                        break;
                     }
                 }
-                records.push(sr);
-            }
+            })
+        })
+        .collect();
+    for worker in workers {
+        if worker.join().is_err() {
+            let _ = sender.blocking_send(Err("State reader thread panicked".to_string()));
         }
     }
 
-    let mut genesis_config = genesis_config.clone();
-    genesis_config.genesis_height = genesis_height;
-    genesis_config.validators = validators
+    info!(
+        target: INDEXER_FOR_WALLET,
+        "State is read in {:?} ({}, {} jobs): {} trie entries scanned, {} records found",
+        started_at.elapsed(),
+        if full_scan { "full trie scan" } else { "prefix scan" },
+        jobs,
+        entries_scanned.load(Ordering::SeqCst),
+        records_found.load(Ordering::SeqCst)
+    );
+}
+
+/// Reads every shard on its own thread and sends its records to its own sender, so the records
+/// of every receiver are in the trie order, i.e. ordered by account. All the receivers are
+/// expected to be read at once, the shard waits while its channel is full.
+/// If a shard can't be read, the error is sent instead of the rest of its records
+pub(crate) fn stream_state_records_by_shard(
+    runtime: NightshadeRuntime,
+    state_roots: Vec<StateRoot>,
+    columns: &[u8],
+    full_scan: bool,
+    batch_size: usize,
+    senders: Vec<tokio::sync::mpsc::Sender<StateRecordsBatch>>,
+) {
+    let runtime = Arc::new(runtime);
+    let workers: Vec<_> = state_roots
         .into_iter()
-        .map(|(account_id, (public_key, amount))| AccountInfo {
-            account_id,
-            public_key,
-            amount,
+        .zip(senders)
+        .enumerate()
+        .map(|(shard_id, (state_root, sender))| {
+            let runtime = runtime.clone();
+            let columns = columns.to_vec();
+            let worker_sender = sender.clone();
+            let worker = std::thread::spawn(move || {
+                if let Err(err) = stream_shard_records(
+                    &runtime,
+                    shard_id as ShardId,
+                    &state_root,
+                    &columns,
+                    full_scan,
+                    batch_size,
+                    &worker_sender,
+                ) {
+                    let _ = worker_sender.blocking_send(Err(format!(
+                        "Failed to read the state of shard #{}: {}",
+                        shard_id, err
+                    )));
+                }
+            });
+            (worker, sender)
         })
         .collect();
-    Genesis::new(genesis_config, records.into())
+    // The senders are kept until the workers are joined, so the channel of a panicked worker
+    // is not closed as if the shard was read
+    for (worker, sender) in workers {
+        if worker.join().is_err() {
+            let _ = sender.blocking_send(Err("State reader thread panicked".to_string()));
+        }
+    }
+}
+
+/// The iterator seeks to the columns' prefixes, so the rest of the trie (e.g. contract code
+/// and data) is not read at all unless `full_scan` is set.
+/// Returns the amounts of the scanned trie entries and the found records,
+/// `None` if the receiver is dropped and there is no need to continue
+/// or an error if the trie can't be read
+fn stream_shard_records(
+    runtime: &NightshadeRuntime,
+    shard_id: ShardId,
+    state_root: &StateRoot,
+    columns: &[u8],
+    full_scan: bool,
+    batch_size: usize,
+    sender: &tokio::sync::mpsc::Sender<StateRecordsBatch>,
+) -> Result<Option<(u64, u64)>, String> {
+    let started_at = std::time::Instant::now();
+    let (mut entries_scanned, mut records_found) = (0u64, 0u64);
+    let trie = runtime.get_trie_for_shard(shard_id);
+    let trie = &trie;
+    let new_trie_iterator = move || {
+        TrieIterator::new(trie, state_root)
+            .map_err(|err| format!("Failed to open the trie: {:?}", err))
+    };
+    let read_entry = |item: Result<(Vec<u8>, Vec<u8>), _>| {
+        item.map_err(|err| format!("Failed to read the trie entry: {:?}", err))
+    };
+
+    let entries: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>), String>> + '_> = if full_scan {
+        Box::new(new_trie_iterator()?.map(read_entry))
+    } else {
+        let mut column_entries = Vec::with_capacity(columns.len());
+        for column in columns {
+            let mut trie_iterator = new_trie_iterator()?;
+            trie_iterator
+                .seek([*column])
+                .map_err(|err| format!("Failed to seek column {}: {:?}", column, err))?;
+            column_entries.push(trie_iterator.map(read_entry).take_while(move |entry| {
+                entry
+                    .as_ref()
+                    .map_or(true, |(key, _)| key.first() == Some(column))
+            }));
+        }
+        Box::new(column_entries.into_iter().flatten())
+    };
+
+    let mut batch = Vec::with_capacity(batch_size);
+    for entry in entries {
+        let (key, value) = entry?;
+        entries_scanned += 1;
+        if entries_scanned % PROGRESS_REPORT_ENTRIES == 0 {
+            info!(
+                target: INDEXER_FOR_WALLET,
+                "Shard #{}: {} trie entries scanned, {} records found so far",
+                shard_id,
+                entries_scanned,
+                records_found
+            );
+        }
+        let column = key.first().copied();
+        if let Some(record) = StateRecord::from_raw_key_value(key, value) {
+            if !column.map_or(false, |column| columns.contains(&column)) {
+                continue;
+            }
+            records_found += 1;
+            batch.push(record);
+            if batch.len() >= batch_size {
+                let full_batch = std::mem::replace(&mut batch, Vec::with_capacity(batch_size));
+                if sender.blocking_send(Ok(full_batch)).is_err() {
+                    return Ok(None);
+                }
+            }
+        }
+    }
+    if !batch.is_empty() && sender.blocking_send(Ok(batch)).is_err() {
+        return Ok(None);
+    }
+
+    info!(
+        target: INDEXER_FOR_WALLET,
+        "Shard #{}: {} trie entries scanned, {} records found in {:?}",
+        shard_id,
+        entries_scanned,
+        records_found,
+        started_at.elapsed()
+    );
+    Ok(Some((entries_scanned, records_found)))
 }
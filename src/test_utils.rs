@@ -0,0 +1,193 @@
+//! Synthetic chain data for the tests. Only the fields read by the indexer are meaningful
+
+use std::sync::Once;
+
+use diesel::{Connection, PgConnection};
+
+use near_crypto::{KeyType, PublicKey, SecretKey, Signature};
+use near_indexer::near_primitives::hash::{hash, CryptoHash};
+use near_indexer::near_primitives::views::{
+    AccessKeyPermissionView, AccessKeyView, ActionView, BlockHeaderView, ReceiptEnumView,
+    ReceiptView,
+};
+
+embed_migrations!();
+
+static MIGRATIONS: Once = Once::new();
+
+/// Connection to the database set by `DATABASE_URL` with all the migrations applied.
+/// The tests using it are ignored by default, run them with `cargo test -- --ignored`
+pub(crate) fn test_connection() -> PgConnection {
+    dotenv::dotenv().ok();
+    let database_url =
+        std::env::var("DATABASE_URL").expect("DATABASE_URL must be set to run database tests");
+    let conn = PgConnection::establish(&database_url).expect("Failed to connect to database");
+    MIGRATIONS.call_once(|| {
+        embedded_migrations::run(&conn).expect("Failed to run migrations");
+    });
+    conn
+}
+
+pub(crate) fn public_key(seed: &str) -> PublicKey {
+    SecretKey::from_seed(KeyType::ED25519, seed).public_key()
+}
+
+pub(crate) fn crypto_hash(seed: &str) -> CryptoHash {
+    hash(seed.as_bytes())
+}
+
+/// Header of the block `seed` at `height`, the timestamp is derived from the height
+pub(crate) fn block_header(
+    seed: &str,
+    height: u64,
+    prev_hash: CryptoHash,
+    last_final_block: CryptoHash,
+) -> BlockHeaderView {
+    let timestamp = height * 1_000_000_000;
+    serde_json::from_value(serde_json::json!({
+        "height": height,
+        "epoch_id": CryptoHash::default(),
+        "next_epoch_id": CryptoHash::default(),
+        "hash": crypto_hash(seed),
+        "prev_hash": prev_hash,
+        "prev_state_root": CryptoHash::default(),
+        "chunk_receipts_root": CryptoHash::default(),
+        "chunk_headers_root": CryptoHash::default(),
+        "chunk_tx_root": CryptoHash::default(),
+        "outcome_root": CryptoHash::default(),
+        "chunks_included": 1,
+        "challenges_root": CryptoHash::default(),
+        "timestamp": timestamp,
+        "timestamp_nanosec": timestamp.to_string(),
+        "random_value": CryptoHash::default(),
+        "validator_proposals": [],
+        "chunk_mask": [true],
+        "gas_price": "0",
+        "rent_paid": "0",
+        "validator_reward": "0",
+        "total_supply": "0",
+        "challenges_result": [],
+        "last_final_block": last_final_block,
+        "last_ds_final_block": last_final_block,
+        "next_bp_hash": CryptoHash::default(),
+        "block_merkle_root": CryptoHash::default(),
+        "approvals": [],
+        "signature": Signature::empty(KeyType::ED25519),
+        "latest_protocol_version": 1,
+    }))
+    .expect("Synthetic block header is a valid BlockHeaderView")
+}
+
+pub(crate) fn add_full_access_key(key_seed: &str) -> ActionView {
+    ActionView::AddKey {
+        public_key: public_key(key_seed),
+        access_key: AccessKeyView {
+            nonce: 0,
+            permission: AccessKeyPermissionView::FullAccess,
+        },
+    }
+}
+
+pub(crate) fn delete_key(key_seed: &str) -> ActionView {
+    ActionView::DeleteKey {
+        public_key: public_key(key_seed),
+    }
+}
+
+pub(crate) fn delete_account() -> ActionView {
+    ActionView::DeleteAccount {
+        beneficiary_id: "beneficiary.near".parse().unwrap(),
+    }
+}
+
+/// Action receipt `seed` signed by the receiver itself
+pub(crate) fn action_receipt(
+    seed: &str,
+    receiver_id: &str,
+    actions: Vec<ActionView>,
+) -> ReceiptView {
+    ReceiptView {
+        predecessor_id: receiver_id.parse().unwrap(),
+        receiver_id: receiver_id.parse().unwrap(),
+        receipt_id: crypto_hash(seed),
+        receipt: ReceiptEnumView::Action {
+            signer_id: receiver_id.parse().unwrap(),
+            signer_public_key: public_key(receiver_id),
+            gas_price: 0,
+            output_data_receivers: vec![],
+            input_data_ids: vec![],
+            actions,
+        },
+    }
+}
+
+/// Block with a single chunk holding the receipts and the outcomes of the receipts,
+/// the outcome is either successful or not
+pub(crate) fn streamer_message(
+    header: BlockHeaderView,
+    receipts: Vec<ReceiptView>,
+    outcomes: Vec<(ReceiptView, bool)>,
+) -> near_indexer::StreamerMessage {
+    let receipt_execution_outcomes: Vec<serde_json::Value> = outcomes
+        .into_iter()
+        .map(|(receipt, is_success)| {
+            serde_json::json!({
+                "execution_outcome": {
+                    "proof": [],
+                    "block_hash": header.hash,
+                    "id": receipt.receipt_id,
+                    "outcome": {
+                        "logs": [],
+                        "receipt_ids": [],
+                        "gas_burnt": 0,
+                        "tokens_burnt": "0",
+                        "executor_id": receipt.receiver_id,
+                        // Unknown status is handled as failure
+                        "status": if is_success {
+                            serde_json::json!({ "SuccessValue": "" })
+                        } else {
+                            serde_json::json!("Unknown")
+                        },
+                        "metadata": { "version": 1, "gas_profile": null },
+                    },
+                },
+                "receipt": receipt,
+            })
+        })
+        .collect();
+    serde_json::from_value(serde_json::json!({
+        "block": {
+            "author": "test.near",
+            "header": header,
+            "chunks": [],
+        },
+        "chunks": [{
+            "author": "test.near",
+            "header": {
+                "chunk_hash": CryptoHash::default(),
+                "prev_block_hash": header.prev_hash,
+                "outcome_root": CryptoHash::default(),
+                "prev_state_root": CryptoHash::default(),
+                "encoded_merkle_root": CryptoHash::default(),
+                "encoded_length": 0,
+                "height_created": header.height,
+                "height_included": header.height,
+                "shard_id": 0,
+                "gas_used": 0,
+                "gas_limit": 0,
+                "rent_paid": "0",
+                "validator_reward": "0",
+                "balance_burnt": "0",
+                "outgoing_receipts_root": CryptoHash::default(),
+                "tx_root": CryptoHash::default(),
+                "validator_proposals": [],
+                "signature": Signature::empty(KeyType::ED25519),
+            },
+            "transactions": [],
+            "receipts": receipts,
+            "receipt_execution_outcomes": receipt_execution_outcomes,
+        }],
+        "state_changes": [],
+    }))
+    .expect("Synthetic streamer message is a valid StreamerMessage")
+}
@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+
+use bigdecimal::BigDecimal;
+use diesel::sql_types::{Numeric, Text};
+use diesel::{Connection, PgConnection, RunQueryDsl};
+use futures::{Stream, StreamExt};
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::configs::VerifyArgs;
+use crate::db::enums::AccessKeyAction;
+use crate::db::{establish_single_connection, AccessKey, CurrentAccessKey, StateDump};
+use crate::dump_state::{self, dump_state_receipt_hash, GENESIS_RECEIPT_HASH};
+use crate::INDEXER_FOR_WALLET;
+
+/// Amount of the differences of every kind printed to the log, the rest is in the `--output` file
+const REPORTED_IN_LOG: usize = 10;
+/// Amount of the database rows fetched at once
+const DATABASE_PAGE_SIZE: usize = 5000;
+/// Amount of the database pages read ahead of the comparison
+const DATABASE_PAGES_IN_FLIGHT: usize = 10;
+
+#[derive(Serialize)]
+struct PermissionMismatch {
+    state: AccessKey,
+    database: AccessKey,
+}
+
+/// Differences between the access keys in the database and in the state
+#[derive(Serialize)]
+struct DriftReport {
+    block_height: near_indexer::near_primitives::types::BlockHeight,
+    block_hash: String,
+    /// Present in the state, but not in the database
+    missing: Vec<AccessKey>,
+    /// Present in the database, but not in the state
+    extra: Vec<AccessKey>,
+    /// Present in both, but with different permission (allowance is not compared)
+    permission_mismatches: Vec<PermissionMismatch>,
+}
+
+impl DriftReport {
+    fn has_drift(&self) -> bool {
+        !self.missing.is_empty() || !self.extra.is_empty() || !self.permission_mismatches.is_empty()
+    }
+
+    /// Compares the keys of the same account, the database keys are mapped by the public key
+    fn compare_account(
+        &mut self,
+        state_access_keys: Vec<AccessKey>,
+        mut database_access_keys: HashMap<String, AccessKey>,
+    ) {
+        for access_key in state_access_keys {
+            match database_access_keys.remove(&access_key.public_key) {
+                Some(database_access_key)
+                    if matches!(database_access_key.action, AccessKeyAction::Add) =>
+                {
+                    if !dump_state::is_same_permission(
+                        &CurrentAccessKey::from(&database_access_key),
+                        &access_key,
+                    ) {
+                        self.permission_mismatches.push(PermissionMismatch {
+                            state: access_key,
+                            database: database_access_key,
+                        });
+                    }
+                }
+                _ => self.missing.push(access_key),
+            }
+        }
+        self.extra.extend(
+            database_access_keys
+                .into_iter()
+                .map(|(_, access_key)| access_key)
+                .filter(|access_key| matches!(access_key.action, AccessKeyAction::Add)),
+        );
+    }
+
+    fn log(&self) {
+        info!(
+            target: INDEXER_FOR_WALLET,
+            "Verified access keys at block #{} ({}): {} missing, {} extra, {} with different permission",
+            self.block_height,
+            self.block_hash,
+            self.missing.len(),
+            self.extra.len(),
+            self.permission_mismatches.len()
+        );
+        for access_key in self.missing.iter().take(REPORTED_IN_LOG) {
+            warn!(
+                target: INDEXER_FOR_WALLET,
+                "Missing in database: {} {}", access_key.account_id, access_key.public_key
+            );
+        }
+        for access_key in self.extra.iter().take(REPORTED_IN_LOG) {
+            warn!(
+                target: INDEXER_FOR_WALLET,
+                "Absent in state: {} {} (receipt {})",
+                access_key.account_id,
+                access_key.public_key,
+                access_key.receipt_hash
+            );
+        }
+        for mismatch in self.permission_mismatches.iter().take(REPORTED_IN_LOG) {
+            warn!(
+                target: INDEXER_FOR_WALLET,
+                "Different permission: {} {} (receipt {})",
+                mismatch.database.account_id,
+                mismatch.database.public_key,
+                mismatch.database.receipt_hash
+            );
+        }
+    }
+}
+
+/// Sends the latest successful actions known at `height` to `sender` in pages ordered by
+/// `account_id` in byte order, as the state is. Only the final rows are read, the state
+/// never has the keys of the blocks which may be rolled back. The rows are read with a cursor,
+/// so the database sorts them once and only the current pages are kept in memory.
+/// The state doesn't include the changes of the block itself, but includes the keys
+/// dumped at this height
+fn stream_database_access_keys(
+    height: near_indexer::near_primitives::types::BlockHeight,
+    sender: mpsc::Sender<Result<Vec<AccessKey>, String>>,
+) {
+    let read_pages = |conn: &PgConnection| {
+        conn.transaction::<_, diesel::result::Error, _>(|| {
+            diesel::sql_query(
+                r#"
+                DECLARE verified_access_keys NO SCROLL CURSOR FOR
+                SELECT DISTINCT ON (account_id COLLATE "C", public_key) *
+                FROM access_keys
+                WHERE status = 'SUCCESS'
+                    AND is_final
+                    AND (
+                        block_height < $1
+                        OR (block_height = $1 AND receipt_hash IN ($2, $3))
+                    )
+                ORDER BY account_id COLLATE "C", public_key, block_height DESC, action_index DESC
+                "#,
+            )
+            .bind::<Numeric, _>(BigDecimal::from(height))
+            .bind::<Text, _>(GENESIS_RECEIPT_HASH)
+            .bind::<Text, _>(dump_state_receipt_hash(height))
+            .execute(conn)?;
+            loop {
+                let page: Vec<AccessKey> = diesel::sql_query(format!(
+                    "FETCH {} FROM verified_access_keys",
+                    DATABASE_PAGE_SIZE
+                ))
+                .load(conn)?;
+                if page.is_empty() || sender.blocking_send(Ok(page)).is_err() {
+                    return Ok(());
+                }
+            }
+        })
+        .map_err(|err| format!("Failed to read access keys from database: {}", err))
+    };
+    if let Err(err) = establish_single_connection().and_then(|conn| read_pages(&conn)) {
+        let _ = sender.blocking_send(Err(err));
+    }
+}
+
+/// Reads the next access key of the stream, `None` at the end of the stream
+async fn next_access_key(
+    access_keys: &mut (impl Stream<Item = Result<AccessKey, String>> + Unpin),
+) -> Result<Option<AccessKey>, String> {
+    access_keys.next().await.transpose()
+}
+
+/// Compares the access keys in the state with the final ones in the database at the same
+/// height ("last successful action wins") and reports the differences. Both sides are streamed
+/// ordered by account and merged, so only the keys of the current account are kept in memory.
+/// Returns `true` if the database differs from the state or an error if the requested block
+/// can't be loaded or any side can't be read
+pub(crate) async fn verify_access_keys(
+    home_dir: std::path::PathBuf,
+    near_config: near_indexer::NearConfig,
+    args: VerifyArgs,
+) -> Result<bool, String> {
+    let (runtime, state_roots, block_header) =
+        dump_state::load_trie(&home_dir, &near_config, &args.state)?;
+    let height = block_header.height();
+
+    let (sender, receiver) = mpsc::channel(DATABASE_PAGES_IN_FLIGHT);
+    let database_reader = std::thread::spawn(move || stream_database_access_keys(height, sender));
+    let mut database_access_keys = tokio_stream::wrappers::ReceiverStream::new(receiver).flat_map(
+        |page: Result<Vec<AccessKey>, String>| {
+            futures::stream::iter(match page {
+                Ok(access_keys) => access_keys.into_iter().map(Ok).collect::<Vec<_>>(),
+                Err(err) => vec![Err(err)],
+            })
+        },
+    );
+    let (state_reader, mut state_access_keys) = dump_state::stream_state_access_keys_by_account(
+        runtime,
+        state_roots,
+        StateDump::from(&block_header),
+        dump_state_receipt_hash(height),
+        &args.state,
+    );
+
+    let mut report = DriftReport {
+        block_height: height,
+        block_hash: block_header.hash().to_string(),
+        missing: vec![],
+        extra: vec![],
+        permission_mismatches: vec![],
+    };
+    let mut state_access_key = next_access_key(&mut state_access_keys).await?;
+    let mut database_access_key = next_access_key(&mut database_access_keys).await?;
+    loop {
+        let account_id = match (&state_access_key, &database_access_key) {
+            (Some(state), Some(database)) => {
+                std::cmp::min(&state.account_id, &database.account_id).clone()
+            }
+            (Some(state), None) => state.account_id.clone(),
+            (None, Some(database)) => database.account_id.clone(),
+            (None, None) => break,
+        };
+        let mut account_state_keys: Vec<AccessKey> = vec![];
+        while let Some(access_key) = state_access_key.take() {
+            if access_key.account_id != account_id {
+                state_access_key = Some(access_key);
+                break;
+            }
+            account_state_keys.push(access_key);
+            state_access_key = next_access_key(&mut state_access_keys).await?;
+        }
+        let mut account_database_keys: HashMap<String, AccessKey> = HashMap::new();
+        while let Some(access_key) = database_access_key.take() {
+            if access_key.account_id != account_id {
+                database_access_key = Some(access_key);
+                break;
+            }
+            account_database_keys.insert(access_key.public_key.clone(), access_key);
+            database_access_key = next_access_key(&mut database_access_keys).await?;
+        }
+        report.compare_account(account_state_keys, account_database_keys);
+    }
+    state_reader
+        .join()
+        .map_err(|_| "State reader thread panicked".to_string())?;
+    database_reader
+        .join()
+        .map_err(|_| "Database reader thread panicked".to_string())?;
+
+    report.log();
+    if let Some(output) = args.output {
+        let file = std::fs::File::create(&output).expect("Failed to create report file");
+        serde_json::to_writer_pretty(std::io::BufWriter::new(file), &report)
+            .expect("Failed to write report file");
+        info!(
+            target: INDEXER_FOR_WALLET,
+            "The report is written to {}",
+            output.display()
+        );
+    }
+    Ok(report.has_drift())
+}
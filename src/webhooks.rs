@@ -0,0 +1,519 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_diesel::dsl::AsyncRunQueryDsl;
+use diesel::{BoolExpressionMethods, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use hmac::{Hmac, Mac, NewMac};
+use itertools::Itertools;
+use serde::Serialize;
+use sha2::Sha256;
+use tokio::time;
+use tracing::{debug, error, info, warn};
+
+use crate::db::enums::{ExecutionStatus, WebhookDeliveryStatus};
+use crate::db::{AccessKey, NewWebhookDelivery, Webhook, WebhookDelivery};
+use crate::{metrics, schema, INDEXER_FOR_WALLET, INTERVAL};
+
+/// Header with hex encoded HMAC-SHA256 of the request body signed by the webhook secret
+const SIGNATURE_HEADER: &str = "X-Indexer-Signature";
+/// Header with the id of the delivery, it is the same for all the attempts
+const DELIVERY_ID_HEADER: &str = "X-Indexer-Delivery";
+/// How often the queue is checked when there is nothing to deliver
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Delay before the first retry, it is doubled after every failed attempt
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_RETRY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const MAX_ATTEMPTS: i32 = 10;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const DELIVERY_BATCH_SIZE: i64 = 100;
+
+#[derive(Serialize)]
+struct WebhookPayload<'a> {
+    webhook_id: i32,
+    access_key: &'a AccessKey,
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("System time is after unix epoch")
+        .as_millis() as i64
+}
+
+/// Queues the deliveries of the webhooks matching the final access key actions which became
+/// SUCCESS. Is expected to be called within the transaction which applies the final block,
+/// so the deliveries are queued exactly once and never for the blocks which are rolled back
+pub(crate) fn enqueue_deliveries(
+    conn: &PgConnection,
+    access_keys: &[AccessKey],
+) -> diesel::QueryResult<()> {
+    let succeeded_access_keys: Vec<&AccessKey> = access_keys
+        .iter()
+        .filter(|access_key| {
+            access_key.is_final && matches!(access_key.status, ExecutionStatus::Success)
+        })
+        .collect();
+    if succeeded_access_keys.is_empty() {
+        return Ok(());
+    }
+
+    let account_ids: Vec<String> = succeeded_access_keys
+        .iter()
+        .map(|access_key| access_key.account_id.clone())
+        .unique()
+        .collect();
+    let public_keys: Vec<String> = succeeded_access_keys
+        .iter()
+        .map(|access_key| access_key.public_key.clone())
+        .unique()
+        .collect();
+    let webhooks: Vec<Webhook> = schema::webhooks::table
+        .filter(
+            schema::webhooks::dsl::account_id
+                .eq_any(account_ids)
+                .or(schema::webhooks::dsl::public_key.eq_any(public_keys)),
+        )
+        .load(conn)?;
+    if webhooks.is_empty() {
+        return Ok(());
+    }
+
+    let now = now_millis();
+    let deliveries: Vec<NewWebhookDelivery> = succeeded_access_keys
+        .iter()
+        .flat_map(|access_key| {
+            webhooks
+                .iter()
+                .filter(move |webhook| webhook.matches(access_key))
+                .map(move |webhook| NewWebhookDelivery {
+                    webhook_id: webhook.id,
+                    receipt_hash: access_key.receipt_hash.clone(),
+                    account_id: access_key.account_id.clone(),
+                    public_key: access_key.public_key.clone(),
+                    action: access_key.action.clone(),
+                    payload: serde_json::to_string(&WebhookPayload {
+                        webhook_id: webhook.id,
+                        access_key,
+                    })
+                    .expect("Webhook payload is always serializable"),
+                    status: WebhookDeliveryStatus::Pending,
+                    attempts: 0,
+                    next_attempt_at: now,
+                })
+        })
+        .collect();
+    if deliveries.is_empty() {
+        return Ok(());
+    }
+    info!(
+        target: INDEXER_FOR_WALLET,
+        "Queueing {} webhook deliveries",
+        deliveries.len()
+    );
+    diesel::insert_into(schema::webhook_deliveries::table)
+        .values(deliveries)
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+    Ok(())
+}
+
+fn sign(secret: &str, payload: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_varkey(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(payload.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Sends the payload to the webhook. Returns the response status code if there was a response
+/// and the error if the request failed or the response status is not successful
+async fn send(
+    client: &awc::Client,
+    webhook: &Webhook,
+    delivery: &WebhookDelivery,
+) -> (Option<i32>, Result<(), String>) {
+    let response = client
+        .post(webhook.url.as_str())
+        .insert_header(("Content-Type", "application/json"))
+        .insert_header((
+            SIGNATURE_HEADER,
+            format!("sha256={}", sign(&webhook.secret, &delivery.payload)),
+        ))
+        .insert_header((DELIVERY_ID_HEADER, delivery.id.to_string()))
+        .send_body(delivery.payload.clone())
+        .await;
+    match response {
+        Ok(response) if response.status().is_success() => {
+            (Some(response.status().as_u16().into()), Ok(()))
+        }
+        Ok(response) => (
+            Some(response.status().as_u16().into()),
+            Err(format!("Unexpected response status {}", response.status())),
+        ),
+        Err(err) => (None, Err(err.to_string())),
+    }
+}
+
+/// Status of the delivery after `attempts` failed attempts and the delay before the next one.
+/// The delay is doubled after every failed attempt
+fn retry_schedule(attempts: i32) -> (WebhookDeliveryStatus, Duration) {
+    let status = if attempts >= MAX_ATTEMPTS {
+        WebhookDeliveryStatus::Failed
+    } else {
+        WebhookDeliveryStatus::Pending
+    };
+    let retry_interval = std::cmp::min(
+        RETRY_INTERVAL * 2u32.saturating_pow((attempts - 1) as u32),
+        MAX_RETRY_INTERVAL,
+    );
+    (status, retry_interval)
+}
+
+/// Stores the result of the delivery attempt and schedules the next one with exponential backoff
+async fn record_attempt(
+    pool: &actix_diesel::Database<PgConnection>,
+    delivery: &WebhookDelivery,
+    response_status: Option<i32>,
+    result: Result<(), String>,
+) {
+    let attempts = delivery.attempts + 1;
+    let now = now_millis();
+    let (status, next_attempt_at, delivered_at, last_error) = match result {
+        Ok(()) => (WebhookDeliveryStatus::Delivered, now, Some(now), None),
+        Err(err) => {
+            let (status, retry_interval) = retry_schedule(attempts);
+            if status == WebhookDeliveryStatus::Failed {
+                error!(
+                    target: INDEXER_FOR_WALLET,
+                    "Webhook delivery #{} failed after {} attempts: {}", delivery.id, attempts, err
+                );
+            } else {
+                warn!(
+                    target: INDEXER_FOR_WALLET,
+                    "Webhook delivery #{} attempt {} failed: {}", delivery.id, attempts, err
+                );
+            }
+            (
+                status,
+                now + retry_interval.as_millis() as i64,
+                None,
+                Some(err),
+            )
+        }
+    };
+
+    loop {
+        match diesel::update(
+            schema::webhook_deliveries::table
+                .filter(schema::webhook_deliveries::dsl::id.eq(delivery.id)),
+        )
+        .set((
+            schema::webhook_deliveries::dsl::status.eq(status),
+            schema::webhook_deliveries::dsl::attempts.eq(attempts),
+            schema::webhook_deliveries::dsl::next_attempt_at.eq(next_attempt_at),
+            schema::webhook_deliveries::dsl::response_status.eq(response_status),
+            schema::webhook_deliveries::dsl::last_error.eq(last_error.clone()),
+            schema::webhook_deliveries::dsl::delivered_at.eq(delivered_at),
+        ))
+        .execute_async(pool)
+        .await
+        {
+            Ok(_) => break,
+            Err(async_error) => {
+                error!(
+                    target: INDEXER_FOR_WALLET,
+                    "Failed to store webhook delivery #{} attempt, retrying in {} milliseconds... \n {:#?}",
+                    delivery.id,
+                    INTERVAL.as_millis(),
+                    async_error
+                );
+                metrics::DB_RETRIES
+                    .with_label_values(&["store_webhook_delivery"])
+                    .inc();
+                time::sleep(INTERVAL).await;
+            }
+        }
+    }
+}
+
+/// Sends the queued webhook deliveries which are due. Runs forever
+pub(crate) async fn deliver_webhooks(pool: std::sync::Arc<actix_diesel::Database<PgConnection>>) {
+    let client = awc::Client::builder().timeout(REQUEST_TIMEOUT).finish();
+    loop {
+        let due_deliveries: Vec<(WebhookDelivery, Webhook)> =
+            match schema::webhook_deliveries::table
+                .inner_join(schema::webhooks::table)
+                .filter(schema::webhook_deliveries::dsl::status.eq(WebhookDeliveryStatus::Pending))
+                .filter(schema::webhook_deliveries::dsl::next_attempt_at.le(now_millis()))
+                .order(schema::webhook_deliveries::dsl::id)
+                .limit(DELIVERY_BATCH_SIZE)
+                .load_async(&pool)
+                .await
+            {
+                Ok(res) => res,
+                Err(async_error) => {
+                    error!(
+                    target: INDEXER_FOR_WALLET,
+                    "Failed to load webhook deliveries, retrying in {} milliseconds... \n {:#?}",
+                    INTERVAL.as_millis(),
+                    async_error
+                );
+                    metrics::DB_RETRIES
+                        .with_label_values(&["load_webhook_deliveries"])
+                        .inc();
+                    time::sleep(INTERVAL).await;
+                    continue;
+                }
+            };
+        if due_deliveries.is_empty() {
+            time::sleep(POLL_INTERVAL).await;
+            continue;
+        }
+
+        debug!(
+            target: INDEXER_FOR_WALLET,
+            "Sending {} webhook deliveries",
+            due_deliveries.len()
+        );
+        for (delivery, webhook) in due_deliveries {
+            let (response_status, result) = send(&client, &webhook, &delivery).await;
+            record_attempt(&pool, &delivery, response_status, result).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::mpsc;
+
+    use super::*;
+    use crate::db::enums::AccessKeyAction;
+    use crate::test_utils;
+
+    /// Request received by the stub server, the header names are lowercase
+    struct StubRequest {
+        headers: HashMap<String, String>,
+        body: String,
+    }
+
+    fn read_request(stream: &mut TcpStream) -> StubRequest {
+        let mut request: Vec<u8> = vec![];
+        let mut buffer = [0u8; 1024];
+        let mut read_more = |request: &mut Vec<u8>| {
+            let read = stream.read(&mut buffer).unwrap();
+            assert!(read > 0, "Connection is closed before the request is read");
+            request.extend_from_slice(&buffer[..read]);
+        };
+        let headers_end = loop {
+            read_more(&mut request);
+            if let Some(position) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+                break position + 4;
+            }
+        };
+        let headers: HashMap<String, String> = String::from_utf8_lossy(&request[..headers_end])
+            .lines()
+            .skip(1)
+            .filter_map(|line| {
+                let mut header = line.splitn(2, ':');
+                Some((
+                    header.next()?.trim().to_lowercase(),
+                    header.next()?.trim().to_string(),
+                ))
+            })
+            .collect();
+        let content_length: usize = headers
+            .get("content-length")
+            .map_or(0, |content_length| content_length.parse().unwrap());
+        while request.len() < headers_end + content_length {
+            read_more(&mut request);
+        }
+        StubRequest {
+            headers,
+            body: String::from_utf8_lossy(&request[headers_end..headers_end + content_length])
+                .to_string(),
+        }
+    }
+
+    /// Local HTTP server answering every request with `status`. Returns its url and the channel
+    /// receiving the requests
+    fn stub_server(status: u16) -> (String, mpsc::Receiver<StubRequest>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/webhook", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let request = read_request(&mut stream);
+                write!(
+                    stream,
+                    "HTTP/1.1 {} Stub\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    status
+                )
+                .unwrap();
+                if sender.send(request).is_err() {
+                    break;
+                }
+            }
+        });
+        (url, receiver)
+    }
+
+    fn webhook(url: String) -> Webhook {
+        Webhook {
+            id: 1,
+            url,
+            secret: "secret".to_string(),
+            account_id: Some("alice.near".to_string()),
+            public_key: None,
+            action: None,
+            permission: None,
+        }
+    }
+
+    fn delivery(webhook_id: i32) -> NewWebhookDelivery {
+        NewWebhookDelivery {
+            webhook_id,
+            receipt_hash: test_utils::crypto_hash("receipt").to_string(),
+            account_id: "alice.near".to_string(),
+            public_key: test_utils::public_key("k1").to_string(),
+            action: AccessKeyAction::Add,
+            payload: r#"{"webhook_id":1}"#.to_string(),
+            status: WebhookDeliveryStatus::Pending,
+            attempts: 0,
+            next_attempt_at: 0,
+        }
+    }
+
+    #[test]
+    fn signature_is_hmac_sha256() {
+        assert_eq!(
+            sign("key", "The quick brown fox jumps over the lazy dog"),
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+
+    #[test]
+    fn retry_interval_is_doubled_until_delivery_fails() {
+        for attempts in 1..MAX_ATTEMPTS {
+            let (status, retry_interval) = retry_schedule(attempts);
+            assert_eq!(status, WebhookDeliveryStatus::Pending);
+            assert_eq!(retry_interval, RETRY_INTERVAL * (1 << (attempts - 1)));
+        }
+        assert_eq!(
+            retry_schedule(MAX_ATTEMPTS).0,
+            WebhookDeliveryStatus::Failed
+        );
+        assert_eq!(retry_schedule(20).1, MAX_RETRY_INTERVAL);
+    }
+
+    #[test]
+    fn payload_is_signed_by_webhook_secret() {
+        let (url, requests) = stub_server(200);
+        let webhook = webhook(url);
+        let new_delivery = delivery(webhook.id);
+        let delivery = WebhookDelivery {
+            id: 42,
+            webhook_id: new_delivery.webhook_id,
+            receipt_hash: new_delivery.receipt_hash,
+            account_id: new_delivery.account_id,
+            public_key: new_delivery.public_key,
+            action: new_delivery.action,
+            payload: new_delivery.payload,
+            status: new_delivery.status,
+            attempts: new_delivery.attempts,
+            next_attempt_at: new_delivery.next_attempt_at,
+            response_status: None,
+            last_error: None,
+            delivered_at: None,
+        };
+
+        let (response_status, result) = actix::System::new().block_on(async {
+            let client = awc::Client::builder().timeout(REQUEST_TIMEOUT).finish();
+            send(&client, &webhook, &delivery).await
+        });
+        assert_eq!(response_status, Some(200));
+        assert_eq!(result, Ok(()));
+
+        let request = requests.recv().unwrap();
+        assert_eq!(request.body, delivery.payload);
+        assert_eq!(
+            request.headers.get("x-indexer-signature"),
+            Some(&format!("sha256={}", sign("secret", &delivery.payload)))
+        );
+        assert_eq!(
+            request.headers.get("x-indexer-delivery"),
+            Some(&"42".to_string())
+        );
+    }
+
+    /// The attempts are stored by the pool, so the rows are committed and removed afterwards
+    #[test]
+    #[ignore]
+    fn delivery_fails_after_max_attempts() {
+        let conn = test_utils::test_connection();
+        let (url, requests) = stub_server(500);
+        let webhook: Webhook = diesel::insert_into(schema::webhooks::table)
+            .values((
+                schema::webhooks::dsl::url.eq(url),
+                schema::webhooks::dsl::secret.eq("secret"),
+                schema::webhooks::dsl::account_id.eq("alice.near"),
+            ))
+            .get_result(&conn)
+            .unwrap();
+        let delivery_id: i64 = diesel::insert_into(schema::webhook_deliveries::table)
+            .values(delivery(webhook.id))
+            .returning(schema::webhook_deliveries::dsl::id)
+            .get_result(&conn)
+            .unwrap();
+        let load_delivery = || -> WebhookDelivery {
+            schema::webhook_deliveries::table
+                .filter(schema::webhook_deliveries::dsl::id.eq(delivery_id))
+                .first(&conn)
+                .unwrap()
+        };
+
+        let mut attempted_deliveries: Vec<(i64, WebhookDelivery)> = vec![];
+        actix::System::new().block_on(async {
+            let pool = crate::db::establish_connection();
+            let client = awc::Client::builder().timeout(REQUEST_TIMEOUT).finish();
+            for _ in 0..MAX_ATTEMPTS {
+                let delivery = load_delivery();
+                let (response_status, result) = send(&client, &webhook, &delivery).await;
+                let attempted_at = now_millis();
+                record_attempt(&pool, &delivery, response_status, result).await;
+                attempted_deliveries.push((attempted_at, load_delivery()));
+            }
+        });
+        let received_requests = requests.try_iter().count();
+
+        diesel::delete(
+            schema::webhook_deliveries::table
+                .filter(schema::webhook_deliveries::dsl::id.eq(delivery_id)),
+        )
+        .execute(&conn)
+        .unwrap();
+        diesel::delete(schema::webhooks::table.filter(schema::webhooks::dsl::id.eq(webhook.id)))
+            .execute(&conn)
+            .unwrap();
+
+        assert_eq!(received_requests, MAX_ATTEMPTS as usize);
+        for (attempted_at, delivery) in &attempted_deliveries {
+            let (status, retry_interval) = retry_schedule(delivery.attempts);
+            let retry_at = attempted_at + retry_interval.as_millis() as i64;
+            assert!(
+                (retry_at..retry_at + 1000).contains(&delivery.next_attempt_at),
+                "Attempt {} is retried at {}, expected {}",
+                delivery.attempts,
+                delivery.next_attempt_at,
+                retry_at
+            );
+            assert_eq!(delivery.status, status);
+            assert_eq!(delivery.response_status, Some(500));
+            assert!(delivery.delivered_at.is_none());
+        }
+        let (_, last_delivery) = attempted_deliveries.last().unwrap();
+        assert_eq!(last_delivery.attempts, MAX_ATTEMPTS);
+        assert_eq!(last_delivery.status, WebhookDeliveryStatus::Failed);
+    }
+}